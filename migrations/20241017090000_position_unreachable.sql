@@ -0,0 +1,3 @@
+-- Positions out of reach of the current configuration, skipped by automation
+-- but left active.
+alter table positions add column unreachable boolean not null default false;
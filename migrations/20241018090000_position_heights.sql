@@ -0,0 +1,2 @@
+alter table positions add column capture_z unsigned integer;
+alter table positions add column water_z unsigned integer;
@@ -0,0 +1,2 @@
+alter table stages add column water_volume real;
+alter table checks add column water_ml real;
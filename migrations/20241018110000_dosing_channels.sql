@@ -0,0 +1,17 @@
+create table if not exists stage_doses (
+    stage_id    integer not null,
+    channel     text not null,
+    amount_ml   real not null,
+    max_duration integer not null default 60,
+    primary key(stage_id, channel)
+);
+
+-- Water is recorded with the other channels; dosed by duration it has no
+-- target amount.
+create table if not exists check_doses (
+    id          integer not null primary key,
+    check_id    integer not null,
+    channel     text not null,
+    amount_ml   real,
+    delivered_ml real
+);
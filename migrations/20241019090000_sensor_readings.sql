@@ -0,0 +1,10 @@
+create table if not exists sensor_readings (
+    id          integer not null primary key,
+    sensor      text not null,
+    quantity    text not null,
+    value       real not null,
+    created_ts  integer not null
+);
+
+create index if not exists sensor_readings_series
+on sensor_readings(sensor, quantity, created_ts);
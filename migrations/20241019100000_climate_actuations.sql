@@ -0,0 +1,8 @@
+create table if not exists climate_actuations (
+    id          integer not null primary key,
+    controller  text not null,
+    active      boolean not null,
+    value       real,
+    setpoint    real,
+    created_ts  integer not null
+);
@@ -0,0 +1,8 @@
+create table if not exists reservoir_levels (
+    id          integer not null primary key,
+    level_ml    real not null,
+    source      text not null,
+    created_ts  integer not null
+);
+
+alter table checks add column water_note text;
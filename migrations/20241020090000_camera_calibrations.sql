@@ -0,0 +1,12 @@
+create table if not exists camera_calibrations (
+    id          integer not null primary key,
+    m11         real not null,
+    m12         real not null,
+    m21         real not null,
+    m22         real not null,
+    offset_x    real not null,
+    offset_y    real not null,
+    capture_z   integer,
+    residual_mm real not null,
+    created_ts  integer not null
+);
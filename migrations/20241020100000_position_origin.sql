@@ -0,0 +1,3 @@
+-- Coordinates a position had before auto-centering first moved it.
+alter table positions add column origin_x unsigned integer;
+alter table positions add column origin_y unsigned integer;
@@ -0,0 +1,11 @@
+-- Changes a survey suggests, kept until an admin approves or rejects them.
+-- `new` proposes a position at x, y; `missing` flags position_id.
+create table if not exists survey_proposals (
+    id          integer not null primary key,
+    kind        text not null,
+    x           unsigned integer not null,
+    y           unsigned integer not null,
+    class       text,
+    position_id integer,
+    created_ts  integer not null
+);
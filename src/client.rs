@@ -40,15 +40,27 @@ pub async fn start_http() -> anyhow::Result<()> {
     server.at("/action/check").get(action::check);
     server.at("/action/recheck").get(action::recheck);
     server.at("/action/goto").get(action::goto);
+    server.at("/action/home").get(action::home);
+    server.at("/action/calibrate").get(action::calibrate);
+    server.at("/action/survey").get(action::survey);
+    server.at("/action/estop").get(action::estop);
+    server.at("/action/estop/reset").get(action::reset_estop);
+    server
+        .at("/action/reservoir/refill")
+        .get(action::refill_reservoir);
 
     server.at("/login").post(login);
     server.at("/logout").all(logout);
 
     server.at("/create/account").post(create::create_account);
     server.at("/create/position").post(create::create_positions);
+    server.at("/create/grid").post(create::create_grid);
 
     server.at("/update/stage").post(update::stage);
+    server.at("/update/stage/dose").post(update::stage_dose);
     server.at("/update/role").post(update::update_role);
+    server.at("/update/position").post(update::position_heights);
+    server.at("/update/survey").post(update::survey_proposal);
 
     server.at("/delete/position").post(delete::position);
     server.at("/delete/account").post(delete::account);
@@ -60,6 +72,18 @@ pub async fn start_http() -> anyhow::Result<()> {
     server.listen("0.0.0.0:8080").await.map_err(|e| dbg!(e))?;
     Ok(())
 }
+/// Parses an optional number from a form field, empty meaning `None`.
+fn optional_number(value: &str) -> tide::Result<Option<u32>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| tide::Error::new(400, e))
+}
+
 async fn index(req: Request<()>) -> tide::Result {
     if let Some(user) = get_user(&req).await? {
         if user.is_admin {
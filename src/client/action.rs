@@ -71,6 +71,83 @@ pub async fn check(req: Request<()>) -> tide::Result {
     }
 }
 
+pub async fn home(req: Request<()>) -> tide::Result {
+    let user = get_user(&req).await?;
+
+    match user {
+        Some(user) if user.is_admin => {
+            async_std::task::spawn(async move {
+                system::home().await?;
+                Ok(()) as anyhow::Result<()>
+            });
+            Ok(Response::new(200))
+        }
+        _ => Ok(Response::new(403)),
+    }
+}
+
+pub async fn calibrate(req: Request<()>) -> tide::Result {
+    let user = get_user(&req).await?;
+
+    match user {
+        Some(user) if user.is_admin => match system::calibrate_camera().await {
+            Ok(calibration) => Ok(Response::builder(200)
+                .body(format!("Camera calibrated: {calibration}"))
+                .build()),
+            Err(e) => Ok(Response::builder(422)
+                .body(format!("Calibration failed: {e}"))
+                .build()),
+        },
+        _ => Ok(Response::new(403)),
+    }
+}
+
+pub async fn survey(req: Request<()>) -> tide::Result {
+    let user = get_user(&req).await?;
+
+    match user {
+        Some(user) if user.is_admin => match system::start_survey().await {
+            Ok(()) => Ok(Response::new(200)),
+            Err(e) => Ok(Response::builder(409).body(e.to_string()).build()),
+        },
+        _ => Ok(Response::new(403)),
+    }
+}
+
+pub async fn estop(req: Request<()>) -> tide::Result {
+    match get_user(&req).await? {
+        Some(user) => {
+            system::emergency_stop(&format!("requested by {}", user.username));
+            Ok(Response::new(200))
+        }
+        None => Ok(Response::new(403)),
+    }
+}
+
+pub async fn reset_estop(req: Request<()>) -> tide::Result {
+    let user = get_user(&req).await?;
+
+    match user {
+        Some(user) if user.is_admin || user.is_manager => match system::reset_emergency_stop() {
+            Ok(()) => Ok(Response::new(200)),
+            Err(e) => Ok(Response::builder(409).body(e.to_string()).build()),
+        },
+        _ => Ok(Response::new(403)),
+    }
+}
+
+pub async fn refill_reservoir(req: Request<()>) -> tide::Result {
+    let user = get_user(&req).await?;
+
+    match user {
+        Some(user) if user.is_admin || user.is_manager => match system::refill_reservoir().await {
+            Ok(()) => Ok(Response::new(200)),
+            Err(e) => Ok(Response::builder(409).body(e.to_string()).build()),
+        },
+        _ => Ok(Response::new(403)),
+    }
+}
+
 pub async fn goto(req: Request<()>) -> tide::Result {
     let user = get_user(&req).await?;
 
@@ -82,11 +159,18 @@ pub async fn goto(req: Request<()>) -> tide::Result {
     match user {
         Some(user) if user.is_admin => {
             if let Ok(Query { x, y }) = req.query() {
-                let img = async_std::task::spawn(async move {
+                let res = async_std::task::spawn(async move {
                     let img = system::capture_raw_at(x, y).await?;
                     Ok(img) as anyhow::Result<_>
                 })
-                .await?;
+                .await;
+                let img = match res {
+                    Ok(img) => img,
+                    Err(e) => match e.downcast_ref::<system::MoveError>() {
+                        Some(e) => return Ok(Response::builder(422).body(e.to_string()).build()),
+                        None => return Err(e.into()),
+                    },
+                };
                 let response = tide::Response::builder(200)
                     .header("Access-Control-Allow-Origin", "*")
                     .content_type("image/jpeg")
@@ -1,8 +1,11 @@
 use async_std::task::spawn;
-use serde::Deserialize;
-use tide::{Redirect, Request};
+use serde::{Deserialize, Serialize};
+use tide::{Redirect, Request, Response};
 
-use crate::{client::get_user, database, system};
+use crate::{
+    client::{get_user, optional_number},
+    database, system,
+};
 
 pub async fn create_account(mut req: Request<()>) -> tide::Result {
     #[derive(Deserialize)]
@@ -54,11 +57,22 @@ pub async fn create_positions(mut req: Request<()>) -> tide::Result {
     struct Form {
         x: u32,
         y: u32,
+        #[serde(default)]
+        capture_z: String,
+        #[serde(default)]
+        water_z: String,
     }
-    let Form { x, y } = req.body_form().await.map_err(|e| dbg!(e))?;
+    let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
+    let (x, y) = (form.x, form.y);
+    let capture_z = optional_number(&form.capture_z)?;
+    let water_z = optional_number(&form.water_z)?;
     match get_user(&req).await? {
         Some(user) if user.is_admin => {
+            if let Err(e) = system::validate_position(x, y, capture_z, water_z).await {
+                return Ok(Response::builder(422).body(e.to_string()).build());
+            }
             let id = database::upsert_position(x, y).await?;
+            database::update_position_heights(id, capture_z, water_z).await?;
             spawn(async move {
                 system::check_at(id, false).await.ok();
             });
@@ -67,3 +81,110 @@ pub async fn create_positions(mut req: Request<()>) -> tide::Result {
     }
     Ok(Redirect::new("/show/manage/positions").into())
 }
+
+pub async fn create_grid(mut req: Request<()>) -> tide::Result {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Mode {
+        Pitch,
+        Coverage,
+    }
+    #[derive(Deserialize)]
+    struct Form {
+        mode: Mode,
+        x: u32,
+        y: u32,
+        #[serde(default)]
+        rows: String,
+        #[serde(default)]
+        columns: String,
+        #[serde(default)]
+        pitch_x: String,
+        #[serde(default)]
+        pitch_y: String,
+        #[serde(default)]
+        width: String,
+        #[serde(default)]
+        height: String,
+        #[serde(default)]
+        overlap_pct: String,
+        #[serde(default)]
+        capture_z: String,
+        #[serde(default)]
+        water_z: String,
+        #[serde(default)]
+        preview: bool,
+    }
+    #[derive(Serialize)]
+    struct Planned {
+        x: u32,
+        y: u32,
+        /// Why the position is not created, if it is not.
+        skip: Option<String>,
+    }
+    #[derive(Serialize)]
+    struct Preview {
+        positions: Vec<Planned>,
+        fov: Option<(f64, f64)>,
+    }
+
+    let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
+    let required = |value: &str, name: &str| {
+        optional_number(value)?
+            .ok_or_else(|| tide::Error::from_str(400, format!("{name} is required")))
+    };
+    let spec = match form.mode {
+        Mode::Pitch => system::GridSpec::Pitch {
+            x: form.x,
+            y: form.y,
+            rows: required(&form.rows, "rows")?,
+            columns: required(&form.columns, "columns")?,
+            pitch_x: required(&form.pitch_x, "pitch x")?,
+            pitch_y: required(&form.pitch_y, "pitch y")?,
+        },
+        Mode::Coverage => system::GridSpec::Coverage {
+            x: form.x,
+            y: form.y,
+            width: required(&form.width, "width")?,
+            height: required(&form.height, "height")?,
+            overlap_pct: match form.overlap_pct.trim() {
+                "" => 0.0,
+                value => value.parse().map_err(|e| tide::Error::new(400, e))?,
+            },
+        },
+    };
+    let capture_z = optional_number(&form.capture_z)?;
+    let water_z = optional_number(&form.water_z)?;
+
+    match get_user(&req).await? {
+        Some(user) if user.is_admin => {
+            let fov = system::field_of_view(capture_z).await;
+            let points = match spec.plan(fov) {
+                Ok(points) => points,
+                Err(e) => return Ok(Response::builder(422).body(e.to_string()).build()),
+            };
+            let mut positions = Vec::with_capacity(points.len());
+            for (x, y) in points {
+                let skip = match system::validate_position(x, y, capture_z, water_z).await {
+                    Err(e) => Some(e.to_string()),
+                    Ok(()) => database::query_position(None, Some((x, y)))
+                        .await?
+                        .pop()
+                        .map(|pos| format!("position {} is already there", pos.id)),
+                };
+                positions.push(Planned { x, y, skip });
+            }
+            if form.preview {
+                return Ok(Response::builder(200)
+                    .body(tide::Body::from_json(&Preview { positions, fov })?)
+                    .build());
+            }
+            for pos in positions.iter().filter(|pos| pos.skip.is_none()) {
+                let id = database::upsert_position(pos.x, pos.y).await?;
+                database::update_position_heights(id, capture_z, water_z).await?;
+            }
+        }
+        _ => (),
+    }
+    Ok(Redirect::new("/show/manage/positions").into())
+}
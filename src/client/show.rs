@@ -32,10 +32,15 @@ pub enum MainData {
 pub struct DetailPosition {
     current_card: (CheckData, CheckData, StageData),
     history: Vec<(CheckData, StageData)>,
+    /// Sum of the measured volumes over the history.
+    water_used_ml: f64,
+    doses: Vec<database::CheckDoseData>,
 }
 
 pub struct DetailPositions {
     positions: Vec<database::PositionData>,
+    calibration: Option<crate::system::Calibration>,
+    proposals: Vec<database::SurveyProposalData>,
 }
 
 pub struct DetailUsers {
@@ -43,6 +48,8 @@ pub struct DetailUsers {
 }
 pub struct DetailStageConfig {
     stages: Vec<StageData>,
+    doses: Vec<database::StageDoseData>,
+    channels: Vec<String>,
 }
 
 pub struct Dashboard {
@@ -52,6 +59,9 @@ pub struct Dashboard {
         database::StageData,
     )>,
     detail: Vec<DashboardDetail>,
+    /// Latest value of each environmental sensor quantity.
+    readings: Vec<database::SensorReadingData>,
+    reservoir: Option<crate::system::ReservoirStatus>,
 }
 
 struct DashboardDetail {
@@ -94,6 +104,8 @@ pub async fn dashboard(req: Request<()>) -> tide::Result {
                     })
                     .collect(),
                 cards: infos,
+                readings: database::query_latest_sensor_readings().await?,
+                reservoir: crate::system::reservoir_status().await?,
             })
         }
         Some(_) => {
@@ -148,9 +160,12 @@ pub async fn position(req: Request<()>) -> tide::Result {
                     .and_then(|current| Some((current.0, last_water?, current.1)));
 
                 if let Some(current_check) = current {
+                    let water_used_ml = infos.iter().filter_map(|(check, _)| check.water_ml).sum();
                     MainData::Position(DetailPosition {
                         current_card: current_check,
                         history: infos,
+                        water_used_ml,
+                        doses: database::query_check_doses(id).await?,
                     })
                 } else {
                     return Ok(Redirect::new("/show/dashboard").into());
@@ -203,6 +218,8 @@ pub async fn manage_poss(req: Request<()>) -> tide::Result {
             }
             MainData::PositionManagement(DetailPositions {
                 positions: database::query_position(None, None).await?,
+                calibration: crate::system::camera_calibration().await,
+                proposals: database::query_survey_proposals(None).await?,
             })
         }
         _ => {
@@ -222,6 +239,8 @@ pub async fn manage_stages(req: Request<()>) -> tide::Result {
     let data = match user {
         Some(ref user) if user.is_manager => MainData::StageManagement(DetailStageConfig {
             stages: database::query_stages(None, None).await?,
+            doses: database::query_stage_doses(None).await?,
+            channels: system::dosing_channels().await,
         }),
         _ => {
             //info.replace("Please login as manager".to_owned());
@@ -1,9 +1,9 @@
 use serde::Deserialize;
-use tide::{Redirect, Request};
+use tide::{Redirect, Request, Response};
 
-use crate::database;
+use crate::{database, system};
 
-use super::get_user;
+use super::{get_user, optional_number};
 
 pub async fn update_role(mut req: Request<()>) -> tide::Result {
     if let Some(user) = get_user(&req).await? {
@@ -50,6 +50,8 @@ pub async fn stage(mut req: Request<()>) -> tide::Result {
                 check_period: u32,
                 water_period: u32,
                 water_duration: u32,
+                #[serde(default)]
+                water_volume: String,
             }
             let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
             database::upsert_stage(database::StageData {
@@ -59,6 +61,7 @@ pub async fn stage(mut req: Request<()>) -> tide::Result {
                 check_period: form.check_period as i64,
                 water_duration: form.water_duration as i64,
                 water_period: form.water_period as i64,
+                water_volume: optional_number(&form.water_volume)?.map(f64::from),
             })
             .await
             .map_err(|e| dbg!(e))?;
@@ -67,3 +70,88 @@ pub async fn stage(mut req: Request<()>) -> tide::Result {
     }
     Ok(Redirect::new("/show/manage/stages").into())
 }
+
+/// Sets how much of an additive channel a stage gets, empty or zero
+/// removing it.
+pub async fn stage_dose(mut req: Request<()>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Form {
+        stage_id: i64,
+        channel: String,
+        #[serde(default)]
+        amount_ml: String,
+        #[serde(default)]
+        max_duration: String,
+    }
+    let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
+    let amount_ml = optional_number(&form.amount_ml)?.unwrap_or(0);
+    let max_duration = optional_number(&form.max_duration)?.unwrap_or(60);
+    match get_user(&req).await? {
+        Some(user) if user.is_admin => {
+            if !system::dosing_channels().await.contains(&form.channel) {
+                return Ok(Response::builder(422)
+                    .body(format!("unknown dosing channel {}", form.channel))
+                    .build());
+            }
+            if amount_ml == 0 {
+                database::delete_stage_dose(form.stage_id, &form.channel).await?;
+            } else {
+                database::upsert_stage_dose(database::StageDoseData {
+                    stage_id: form.stage_id,
+                    channel: form.channel,
+                    amount_ml: amount_ml as f64,
+                    max_duration: max_duration as i64,
+                })
+                .await?;
+            }
+        }
+        _ => (),
+    }
+    Ok(Redirect::new("/show/manage/stages").into())
+}
+
+pub async fn position_heights(mut req: Request<()>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Form {
+        id: i64,
+        #[serde(default)]
+        capture_z: String,
+        #[serde(default)]
+        water_z: String,
+    }
+    let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
+    let capture_z = optional_number(&form.capture_z)?;
+    let water_z = optional_number(&form.water_z)?;
+    match get_user(&req).await? {
+        Some(user) if user.is_admin => {
+            let Some(position) = database::query_position(Some(form.id), None).await?.pop() else {
+                return Ok(Response::new(404));
+            };
+            let (x, y) = (position.x as u32, position.y as u32);
+            if let Err(e) = system::validate_position(x, y, capture_z, water_z).await {
+                return Ok(Response::builder(422).body(e.to_string()).build());
+            }
+            database::update_position_heights(form.id, capture_z, water_z).await?;
+        }
+        _ => (),
+    }
+    Ok(Redirect::new("/show/manage/positions").into())
+}
+
+pub async fn survey_proposal(mut req: Request<()>) -> tide::Result {
+    #[derive(Deserialize)]
+    struct Form {
+        id: i64,
+        approve: bool,
+    }
+    let form: Form = req.body_form().await.map_err(|e| dbg!(e))?;
+    match get_user(&req).await? {
+        Some(user) if user.is_admin => {
+            if let Err(e) = system::resolve_survey_proposal(form.id, form.approve).await {
+                return Ok(Response::builder(422).body(e.to_string()).build());
+            }
+        }
+        _ => (),
+    }
+    Ok(Redirect::new("/show/manage/positions").into())
+}
@@ -17,6 +17,13 @@ pub struct PositionData {
     pub active: bool,
     pub x: i64,
     pub y: i64,
+    pub capture_z: Option<i64>,
+    pub water_z: Option<i64>,
+    /// Where the position was created, once auto-centering has moved it.
+    pub origin_x: Option<i64>,
+    pub origin_y: Option<i64>,
+    /// Out of reach of the current configuration, so skipped by automation.
+    pub unreachable: bool,
 }
 #[derive(Debug, Clone)]
 pub struct ImageData {
@@ -31,6 +38,8 @@ pub struct StageData {
     pub check_period: i64,
     pub water_period: i64,
     pub water_duration: i64,
+    /// Target volume in ml; `water_duration` then only caps the pumping.
+    pub water_volume: Option<f64>,
 }
 #[derive(Debug, Clone)]
 pub struct CheckData {
@@ -40,6 +49,76 @@ pub struct CheckData {
     pub image_id: i64,
     pub stage_id: i64,
     pub watered: bool,
+    /// Volume measured by the flow meter.
+    pub water_ml: Option<f64>,
+    /// Why the position was not watered, such as an empty reservoir.
+    pub water_note: Option<String>,
+}
+/// Amount of an additive channel applied at every watering of a stage.
+#[derive(Debug, Clone)]
+pub struct StageDoseData {
+    pub stage_id: i64,
+    pub channel: String,
+    pub amount_ml: f64,
+    /// Longest the channel may pump for, in seconds.
+    pub max_duration: i64,
+}
+/// One measured quantity of an environmental sensor.
+#[derive(Debug, Clone)]
+pub struct SensorReadingData {
+    pub sensor: String,
+    pub quantity: String,
+    pub value: f64,
+    pub created_ts: i64,
+}
+/// Relay switch made by a climate controller, with what it was based on.
+#[derive(Debug, Clone)]
+pub struct ClimateActuationData {
+    pub controller: String,
+    pub active: bool,
+    pub value: Option<f64>,
+    pub setpoint: Option<f64>,
+    pub created_ts: i64,
+}
+/// Volume in the reservoir after a refill, a measurement or a watering.
+#[derive(Debug, Clone)]
+pub struct ReservoirLevelData {
+    pub level_ml: f64,
+    pub source: String,
+    pub created_ts: i64,
+}
+/// Solved pixel to millimetre transform of the camera. `m11`..`m22` map a
+/// pixel offset from the image centre to a bed offset, and `offset_x`,
+/// `offset_y` is where the image centre sits relative to the carriage.
+#[derive(Debug, Clone)]
+pub struct CameraCalibrationData {
+    pub m11: f64,
+    pub m12: f64,
+    pub m21: f64,
+    pub m22: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub capture_z: Option<i64>,
+    pub residual_mm: f64,
+    pub created_ts: i64,
+}
+#[derive(Debug, Clone)]
+pub struct SurveyProposalData {
+    pub id: i64,
+    pub kind: String,
+    pub x: i64,
+    pub y: i64,
+    pub class: Option<String>,
+    pub position_id: Option<i64>,
+    pub created_ts: i64,
+}
+#[derive(Debug, Clone)]
+pub struct CheckDoseData {
+    pub check_id: i64,
+    pub channel: String,
+    /// Target volume, `None` when dosed by duration.
+    pub amount_ml: Option<f64>,
+    pub delivered_ml: Option<f64>,
 }
 #[derive(Debug, Clone)]
 pub struct AccountData {
@@ -146,7 +225,9 @@ select
     max(created_ts) as created_ts,
     stage_id,
     image_id,
-    watered
+    watered,
+    water_ml,
+    water_note
 from checks
 where (?2 = false or watered = true)
 and (?1 is null or position_id = ?1)
@@ -166,6 +247,8 @@ group by (position_id)
             stage_id: obj.stage_id?,
             image_id: obj.image_id?,
             watered: obj.watered?,
+            water_ml: obj.water_ml,
+            water_note: obj.water_note,
         })
     })
     .collect())
@@ -187,6 +270,138 @@ or (id = ?1 and ?2 is null)
     .await?)
 }
 
+pub async fn query_stage_doses(stage_id: Option<i64>) -> anyhow::Result<Vec<StageDoseData>> {
+    Ok(query_as!(
+        StageDoseData,
+        r#"
+select * from stage_doses
+where ?1 is null
+or stage_id = ?1
+order by channel
+        "#,
+        stage_id,
+    )
+    .fetch_all(&*DB)
+    .await?)
+}
+
+pub async fn query_check_doses(position_id: i64) -> anyhow::Result<Vec<CheckDoseData>> {
+    Ok(query_as!(
+        CheckDoseData,
+        r#"
+select
+    check_doses.check_id,
+    check_doses.channel,
+    check_doses.amount_ml,
+    check_doses.delivered_ml
+from check_doses
+join checks on checks.id = check_doses.check_id
+where checks.position_id = ?1
+        "#,
+        position_id,
+    )
+    .fetch_all(&*DB)
+    .await?)
+}
+
+/// Most recent value of every sensor quantity.
+pub async fn query_latest_sensor_readings() -> anyhow::Result<Vec<SensorReadingData>> {
+    Ok(query_as!(
+        SensorReadingData,
+        r#"
+select sensor, quantity, value, created_ts from sensor_readings
+where created_ts = (
+    select max(latest.created_ts) from sensor_readings latest
+    where latest.sensor = sensor_readings.sensor
+    and latest.quantity = sensor_readings.quantity
+)
+group by sensor, quantity
+order by sensor, quantity
+        "#,
+    )
+    .fetch_all(&*DB)
+    .await?)
+}
+
+pub async fn query_latest_sensor_reading(
+    sensor: &str,
+    quantity: &str,
+) -> anyhow::Result<Option<SensorReadingData>> {
+    Ok(query_as!(
+        SensorReadingData,
+        r#"
+select sensor, quantity, value, created_ts from sensor_readings
+where sensor = ?1
+and quantity = ?2
+order by created_ts desc
+limit 1
+        "#,
+        sensor,
+        quantity,
+    )
+    .fetch_optional(&*DB)
+    .await?)
+}
+
+/// Stage most positions were found in at their last check.
+pub async fn query_dominant_stage() -> anyhow::Result<Option<String>> {
+    Ok(query!(
+        r#"
+select stages.stage from checks
+join stages on stages.id = checks.stage_id
+where checks.id in (select max(id) from checks group by position_id)
+group by stages.stage
+order by count(*) desc
+limit 1
+        "#,
+    )
+    .fetch_optional(&*DB)
+    .await?
+    .map(|row| row.stage))
+}
+
+pub async fn query_reservoir_level() -> anyhow::Result<Option<ReservoirLevelData>> {
+    Ok(query_as!(
+        ReservoirLevelData,
+        r#"
+select level_ml, source, created_ts from reservoir_levels
+order by id desc
+limit 1
+        "#,
+    )
+    .fetch_optional(&*DB)
+    .await?)
+}
+
+pub async fn query_camera_calibration() -> anyhow::Result<Option<CameraCalibrationData>> {
+    Ok(query_as!(
+        CameraCalibrationData,
+        r#"
+select m11, m12, m21, m22, offset_x, offset_y, capture_z, residual_mm, created_ts
+from camera_calibrations
+order by id desc
+limit 1
+        "#,
+    )
+    .fetch_optional(&*DB)
+    .await?)
+}
+
+pub async fn query_survey_proposals(id: Option<i64>) -> anyhow::Result<Vec<SurveyProposalData>> {
+    Ok(query_as!(
+        SurveyProposalData,
+        r#"
+select * from survey_proposals
+where ?1 is null
+or id = ?1
+order by id
+        "#,
+        id,
+    )
+    .fetch_all(&*DB)
+    .await?)
+}
+
 pub async fn query_images(id: Option<i64>) -> anyhow::Result<Vec<ImageData>> {
     Ok(query_as!(
         ImageData,
@@ -219,6 +434,62 @@ returning id
     .id)
 }
 
+pub async fn update_position_heights(
+    id: i64,
+    capture_z: Option<u32>,
+    water_z: Option<u32>,
+) -> anyhow::Result<bool> {
+    Ok(query!(
+        r#"
+update positions
+set capture_z = ?2,
+    water_z = ?3
+where id = ?1
+        "#,
+        id,
+        capture_z,
+        water_z,
+    )
+    .execute(&*DB)
+    .await
+    .map(|r| r.rows_affected() == 1)?)
+}
+
+/// Moves a position, keeping the coordinates it was created at.
+pub async fn update_position_xy(id: i64, x: u32, y: u32) -> anyhow::Result<bool> {
+    Ok(query!(
+        r#"
+update positions
+set origin_x = coalesce(origin_x, x),
+    origin_y = coalesce(origin_y, y),
+    x = ?2,
+    y = ?3
+where id = ?1
+        "#,
+        id,
+        x,
+        y,
+    )
+    .execute(&*DB)
+    .await
+    .map(|r| r.rows_affected() == 1)?)
+}
+
+pub async fn update_position_unreachable(id: i64, unreachable: bool) -> anyhow::Result<bool> {
+    Ok(query!(
+        r#"
+update positions
+set unreachable = ?2
+where id = ?1
+        "#,
+        id,
+        unreachable,
+    )
+    .execute(&*DB)
+    .await
+    .map(|r| r.rows_affected() == 1)?)
+}
+
 pub async fn upsert_account(account: AccountData) -> anyhow::Result<i64> {
     Ok(query!(
         r#"
@@ -245,14 +516,16 @@ returning id
 pub async fn upsert_check(check: CheckData) -> anyhow::Result<i64> {
     Ok(query!(
         r#"
-insert into checks (position_id, stage_id, image_id, watered, created_ts)
-values(?1, ?2, ?3, ?4, ?5)
+insert into checks (position_id, stage_id, image_id, watered, created_ts, water_ml, water_note)
+values(?1, ?2, ?3, ?4, ?5, ?6, ?7)
 on conflict(created_ts)
 do update
 set 
     stage_id = ?2,
     image_id = ?3,
-    watered = ?4
+    watered = ?4,
+    water_ml = ?6,
+    water_note = ?7
 returning id
         "#,
         check.position_id,
@@ -260,6 +533,8 @@ returning id
         check.image_id,
         check.watered,
         check.created_ts,
+        check.water_ml,
+        check.water_note,
     )
     .fetch_one(&*DB)
     .await?
@@ -270,32 +545,179 @@ pub async fn upsert_stage(stage: StageData) -> anyhow::Result<StageData> {
     Ok(query_as!(
         StageData,
         r#"
-insert into stages (stage, first_stage, check_period, water_period, water_duration)
-values(?1, ?2, ?3, ?4, ?5)
+insert into stages (stage, first_stage, check_period, water_period, water_duration, water_volume)
+values(?1, ?2, ?3, ?4, ?5, ?6)
 on conflict (stage) 
 do update
 set first_stage = ?2,
     check_period = ?3,
     water_period = ?4,
-    water_duration = ?5
+    water_duration = ?5,
+    water_volume = ?6
 returning 
     id,
     stage,
     first_stage,
     check_period,
     water_period,
-    water_duration
+    water_duration,
+    water_volume
         "#,
         stage.stage,
         stage.first_stage,
         stage.check_period,
         stage.water_period,
         stage.water_duration,
+        stage.water_volume,
     )
     .fetch_one(&*DB)
     .await?)
 }
 
+pub async fn upsert_stage_dose(dose: StageDoseData) -> anyhow::Result<()> {
+    query!(
+        r#"
+insert into stage_doses (stage_id, channel, amount_ml, max_duration)
+values(?1, ?2, ?3, ?4)
+on conflict (stage_id, channel)
+do update
+set amount_ml = ?3, max_duration = ?4
+        "#,
+        dose.stage_id,
+        dose.channel,
+        dose.amount_ml,
+        dose.max_duration,
+    )
+    .execute(&*DB)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_stage_dose(stage_id: i64, channel: &str) -> anyhow::Result<bool> {
+    Ok(query!(
+        r#"
+delete from stage_doses
+where stage_id = ?1 and channel = ?2
+        "#,
+        stage_id,
+        channel,
+    )
+    .execute(&*DB)
+    .await?
+    .rows_affected()
+        == 1)
+}
+
+pub async fn insert_check_dose(dose: CheckDoseData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into check_doses (check_id, channel, amount_ml, delivered_ml)
+values(?1, ?2, ?3, ?4)
+returning id
+        "#,
+        dose.check_id,
+        dose.channel,
+        dose.amount_ml,
+        dose.delivered_ml,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
+pub async fn insert_sensor_reading(reading: SensorReadingData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into sensor_readings (sensor, quantity, value, created_ts)
+values(?1, ?2, ?3, ?4)
+returning id
+        "#,
+        reading.sensor,
+        reading.quantity,
+        reading.value,
+        reading.created_ts,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
+pub async fn insert_climate_actuation(actuation: ClimateActuationData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into climate_actuations (controller, active, value, setpoint, created_ts)
+values(?1, ?2, ?3, ?4, ?5)
+returning id
+        "#,
+        actuation.controller,
+        actuation.active,
+        actuation.value,
+        actuation.setpoint,
+        actuation.created_ts,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
+pub async fn insert_reservoir_level(level: ReservoirLevelData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into reservoir_levels (level_ml, source, created_ts)
+values(?1, ?2, ?3)
+returning id
+        "#,
+        level.level_ml,
+        level.source,
+        level.created_ts,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
+pub async fn insert_camera_calibration(calibration: CameraCalibrationData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into camera_calibrations
+(m11, m12, m21, m22, offset_x, offset_y, capture_z, residual_mm, created_ts)
+values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+returning id
+        "#,
+        calibration.m11,
+        calibration.m12,
+        calibration.m21,
+        calibration.m22,
+        calibration.offset_x,
+        calibration.offset_y,
+        calibration.capture_z,
+        calibration.residual_mm,
+        calibration.created_ts,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
+pub async fn insert_survey_proposal(proposal: SurveyProposalData) -> anyhow::Result<i64> {
+    Ok(query!(
+        r#"
+insert into survey_proposals (kind, x, y, class, position_id, created_ts)
+values(?1, ?2, ?3, ?4, ?5, ?6)
+returning id
+        "#,
+        proposal.kind,
+        proposal.x,
+        proposal.y,
+        proposal.class,
+        proposal.position_id,
+        proposal.created_ts,
+    )
+    .fetch_one(&*DB)
+    .await?
+    .id)
+}
+
 pub async fn insert_image(image: &[u8]) -> anyhow::Result<i64> {
     Ok(query!(
         r#"
@@ -337,6 +759,32 @@ where id = ?1
         == 1)
 }
 
+pub async fn delete_survey_proposal(id: i64) -> anyhow::Result<bool> {
+    Ok(query!(
+        r#"
+delete from survey_proposals
+where id = ?1
+        "#,
+        id,
+    )
+    .execute(&*DB)
+    .await?
+    .rows_affected()
+        == 1)
+}
+
+/// Drops what an earlier survey proposed before a new one reports.
+pub async fn delete_survey_proposals() -> anyhow::Result<u64> {
+    Ok(query!(
+        r#"
+delete from survey_proposals
+        "#,
+    )
+    .execute(&*DB)
+    .await?
+    .rows_affected())
+}
+
 pub async fn delete_account(id: i64) -> anyhow::Result<bool> {
     Ok(query!(
         r#"
@@ -1,7 +1,13 @@
 mod actuator;
 mod camera;
+mod climate;
 mod detector;
+mod grid;
+mod reservoir;
+mod sensor;
+mod survey;
 
+use std::collections::BTreeMap;
 use std::io::{Cursor, Read, Write};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -14,9 +20,19 @@ use serde::{Deserialize, Serialize};
 
 use crate::database::{self};
 
-use self::actuator::ActuatorProfile;
-use self::camera::CameraConfig;
-use detector::DetectorConfig;
+use self::actuator::gpio_pin::{self, InputConfig};
+pub use self::actuator::MoveError;
+use self::actuator::{
+    estop, ActuatorProfile, Dose, SprayArea, ToolOffset, TravelLimits, NOZZLE, WATER,
+};
+pub use self::camera::{Calibration, PixelTransform};
+use self::camera::{CameraConfig, Sample};
+pub use self::grid::GridSpec;
+pub use self::reservoir::ReservoirStatus;
+use climate::ClimateConfig;
+use detector::{DetectionResult, DetectorConfig};
+use reservoir::ReservoirConfig;
+use sensor::SensorConfig;
 
 #[derive(Clone, Debug, Getters)]
 pub struct WaterResult {
@@ -36,14 +52,28 @@ pub struct CaptureResult {
 
 static CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
 static ACTUATOR: Mutex<Option<ActuatorProfile>> = Mutex::new(None);
+/// Copy of the actuator envelope, readable while a move holds `ACTUATOR`.
+static LIMITS: Mutex<Option<TravelLimits>> = Mutex::new(None);
 static CAMERA: Mutex<Option<CameraConfig>> = Mutex::new(None);
+static CALIBRATION: Mutex<Option<Calibration>> = Mutex::new(None);
 static DETECTOR: Mutex<Option<DetectorConfig>> = Mutex::new(None);
+static SENSORS: Mutex<Vec<SensorConfig>> = Mutex::new(Vec::new());
+static CLIMATE: Mutex<BTreeMap<String, ClimateConfig>> = Mutex::new(BTreeMap::new());
+static RESERVOIR: Mutex<Option<ReservoirConfig>> = Mutex::new(None);
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct LocalSystemConfig {
     actuators: ActuatorProfile,
     detector: DetectorConfig,
     camera: CameraConfig,
+    #[serde(default)]
+    inputs: BTreeMap<String, InputConfig>,
+    #[serde(default)]
+    sensors: Vec<SensorConfig>,
+    #[serde(default)]
+    climate: BTreeMap<String, ClimateConfig>,
+    #[serde(default)]
+    reservoir: Option<ReservoirConfig>,
 }
 pub fn timestamp() -> i64 {
     SystemTime::now()
@@ -56,13 +86,93 @@ pub async fn init(config_path: &Path) -> anyhow::Result<()> {
     let mut data = String::new();
     file.read_to_string(&mut data)?;
     let config: LocalSystemConfig = toml::from_str(&data)?;
-    ACTUATOR.lock().await.replace(config.actuators);
+    let mut lines = config.actuators.gpio_lines();
+    for (name, input) in &config.inputs {
+        lines.push((format!("input:{name}"), input.pin.clone()));
+    }
+    for (name, relay) in &config.climate {
+        lines.push((format!("climate:{name}"), relay.pin.clone()));
+    }
+    if let Some(ultrasonic) = config
+        .reservoir
+        .as_ref()
+        .and_then(|r| r.ultrasonic.as_ref())
+    {
+        lines.push(("reservoir:trigger".to_owned(), ultrasonic.trigger.clone()));
+        lines.push(("reservoir:echo".to_owned(), ultrasonic.echo.clone()));
+    }
+    gpio_pin::claim(lines)?;
+    config.actuators.guard_pumps();
+    let mut safe_outputs = config.actuators.backend.safe_outputs();
+    safe_outputs.extend(climate::safe_outputs(&config.climate));
+    estop::set_safe_outputs(safe_outputs);
+    estop::watch(config.actuators.estop.clone());
+    LIMITS
+        .lock()
+        .await
+        .replace(config.actuators.travel_limits());
+    if let Some(reservoir) = &config.reservoir {
+        if reservoir.ultrasonic.is_none() && !config.actuators.backend.measures_flow(WATER) {
+            log::warn!(
+                "The reservoir level cannot be tracked: no ultrasonic sensor, and the water pump \
+                 has neither a flow meter nor ml_per_s; only the float switch can lock it out"
+            );
+        }
+        reservoir.start_polling();
+    }
     DETECTOR.lock().await.replace(config.detector);
     CAMERA.lock().await.replace(config.camera);
     CONFIG_PATH.lock().await.replace(config_path.to_owned());
+    gpio_pin::register_inputs(config.inputs);
+    log_inputs();
+    *SENSORS.lock().await = config.sensors.clone();
+    sensor::start_polling(config.sensors);
+    *CLIMATE.lock().await = config.climate.clone();
+    climate::start(config.climate);
+    *RESERVOIR.lock().await = config.reservoir;
+    match database::query_camera_calibration().await {
+        Ok(calibration) => *CALIBRATION.lock().await = calibration.map(Calibration::from),
+        Err(e) => log::error!("Unable to load the camera calibration: {e}"),
+    }
+    if let Err(e) = flag_unreachable_positions().await {
+        log::error!("Unable to check positions against travel limits: {e}");
+    }
+    // Homed before it is published, so the actuator lock is never held for
+    // the whole run.
+    let mut actuators = config.actuators;
+    let homed = actuators.home().await;
+    ACTUATOR.lock().await.replace(actuators);
+    match homed {
+        Ok(()) => sync_profile().await?,
+        Err(e) => log::error!("Homing at startup failed: {e}"),
+    }
     Ok(())
 }
 
+/// Logs the state of every configured input and each debounced change.
+fn log_inputs() {
+    for name in gpio_pin::inputs().into_keys() {
+        let edges = match gpio_pin::is_active(&name).and_then(|active| {
+            log::info!(
+                "Input {name} is {}",
+                if active { "active" } else { "inactive" }
+            );
+            gpio_pin::edges(&name)
+        }) {
+            Ok(edges) => edges,
+            Err(e) => {
+                log::error!("Unable to watch input {name}: {e}");
+                continue;
+            }
+        };
+        async_std::task::spawn(async move {
+            while let Ok(edge) = edges.recv().await {
+                log::info!("Input {name}: {edge:?}");
+            }
+        });
+    }
+}
+
 async fn sync_profile() -> Result<(), anyhow::Error> {
     let actuators = ACTUATOR.lock().await.clone();
     let detector = DETECTOR.lock().await.clone();
@@ -71,6 +181,10 @@ async fn sync_profile() -> Result<(), anyhow::Error> {
         actuators: actuators.unwrap(),
         detector: detector.unwrap(),
         camera: camera.unwrap(),
+        inputs: gpio_pin::inputs(),
+        sensors: SENSORS.lock().await.clone(),
+        climate: CLIMATE.lock().await.clone(),
+        reservoir: RESERVOIR.lock().await.clone(),
     };
 
     let conf_data = toml::to_string(&config)?;
@@ -83,19 +197,86 @@ async fn sync_profile() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+pub fn emergency_stop(reason: &str) {
+    estop::trigger(reason);
+}
+
+pub fn reset_emergency_stop() -> anyhow::Result<()> {
+    estop::reset()
+}
+
+pub fn is_halted() -> bool {
+    estop::is_halted()
+}
+
+/// Checks that the camera can reach a position at its capture height and
+/// the nozzle at its watering height, without moving.
+pub async fn validate_position(
+    x: u32,
+    y: u32,
+    capture_z: Option<u32>,
+    water_z: Option<u32>,
+) -> Result<(), MoveError> {
+    let limits = LIMITS.lock().await;
+    let limits = limits.as_ref().expect("init must be called");
+    limits.validate_tool(actuator::CAMERA, x, y, capture_z)?;
+    limits.validate_tool(NOZZLE, x, y, water_z)
+}
+
+/// Flags stored positions the current configuration can no longer reach,
+/// and clears the flag on those it can again.
+async fn flag_unreachable_positions() -> anyhow::Result<()> {
+    for pos in database::query_position(None, None).await? {
+        let res = validate_position(
+            pos.x as u32,
+            pos.y as u32,
+            pos.capture_z.map(|z| z as u32),
+            pos.water_z.map(|z| z as u32),
+        )
+        .await;
+        let unreachable = match res {
+            Ok(()) => false,
+            Err(e) => {
+                log::warn!(
+                    "Position {} ({}, {}) unreachable: {e}",
+                    pos.id,
+                    pos.x,
+                    pos.y
+                );
+                true
+            }
+        };
+        if unreachable != pos.unreachable {
+            database::update_position_unreachable(pos.id, unreachable).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn home() -> anyhow::Result<()> {
+    let mut ac = ACTUATOR.lock().await;
+    ac.as_mut().expect("init must be called").home().await?;
+    drop(ac);
+    sync_profile().await?;
+    Ok(())
+}
+
 pub async fn capture_raw() -> anyhow::Result<Vec<u8>> {
     let mut camera = CAMERA.lock().await;
     camera.as_mut().unwrap().capture_raw().await
 }
 pub async fn capture_raw_at(x: u32, y: u32) -> anyhow::Result<Vec<u8>> {
     let mut ac = ACTUATOR.lock().await;
-    ac.as_mut().unwrap().goto(x, y).await?;
+    ac.as_mut()
+        .unwrap()
+        .goto(actuator::CAMERA, x, y, None)
+        .await?;
     capture_raw().await
 }
 
-pub async fn capture_at(x: u32, y: u32) -> anyhow::Result<CaptureResult> {
+pub async fn capture_at(x: u32, y: u32, z: Option<u32>) -> anyhow::Result<CaptureResult> {
     let mut ac = ACTUATOR.lock().await;
-    ac.as_mut().unwrap().goto(x, y).await?;
+    ac.as_mut().unwrap().goto(actuator::CAMERA, x, y, z).await?;
     let mut camera = CAMERA.lock().await;
     let image = camera.as_mut().unwrap().capture().await?;
     drop(ac);
@@ -111,14 +292,149 @@ pub async fn capture_at(x: u32, y: u32) -> anyhow::Result<CaptureResult> {
     sync_profile().await?;
     Ok(res)
 }
-async fn water_at(x: u32, y: u32, dur: Duration) -> anyhow::Result<()> {
+async fn water_at(
+    x: u32,
+    y: u32,
+    z: Option<u32>,
+    area: Option<SprayArea>,
+    doses: &[(String, Dose)],
+) -> anyhow::Result<Vec<Option<f64>>> {
     let mut ac = ACTUATOR.lock().await;
-    ac.as_mut()
+    let delivered = ac
+        .as_mut()
         .expect("init must be called")
-        .water_at(x, y, dur)
+        .water_at(x, y, z, area, doses)
         .await
         .map_err(|e| dbg!(e))?;
-    Ok(())
+    Ok(delivered)
+}
+
+/// Level of the reservoir, `None` when none is configured.
+pub async fn reservoir_status() -> anyhow::Result<Option<ReservoirStatus>> {
+    let reservoir = RESERVOIR.lock().await.clone();
+    match reservoir {
+        Some(reservoir) => Ok(Some(reservoir.status().await?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn refill_reservoir() -> anyhow::Result<()> {
+    let reservoir = RESERVOIR.lock().await.clone();
+    match reservoir {
+        Some(reservoir) => reservoir.refill().await,
+        None => anyhow::bail!("no reservoir configured"),
+    }
+}
+
+/// Why watering must not happen now, if anything.
+async fn water_lockout() -> anyhow::Result<Option<String>> {
+    Ok(reservoir_status().await?.and_then(|status| status.lockout))
+}
+
+/// Maps pixel offsets from the image centre to bed millimetres for captures
+/// at `capture_z`: the solved calibration, else the configured `mm_per_px`.
+/// `None` when the calibration was solved at another height, its scale not
+/// holding there.
+pub async fn pixel_transform(capture_z: Option<u32>) -> Option<PixelTransform> {
+    if let Some(calibration) = CALIBRATION.lock().await.as_ref() {
+        return (calibration.capture_z == capture_z).then_some(calibration.transform);
+    }
+    CAMERA
+        .lock()
+        .await
+        .as_ref()
+        .and_then(|camera| camera.mm_per_px)
+        .map(PixelTransform::scale)
+}
+
+/// Bed area a capture at `capture_z` covers, once the pixel size is known.
+pub async fn field_of_view(capture_z: Option<u32>) -> Option<(f64, f64)> {
+    pixel_transform(capture_z).await.map(camera::field_of_view)
+}
+
+pub async fn camera_calibration() -> Option<Calibration> {
+    CALIBRATION.lock().await.clone()
+}
+
+/// Captures the fiducial from around it, solves scale, rotation and camera
+/// offset, stores the result and moves the camera tool offset to it.
+pub async fn calibrate_camera() -> anyhow::Result<Calibration> {
+    let config = CAMERA
+        .lock()
+        .await
+        .as_ref()
+        .expect("init must be called")
+        .calibration
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("no camera calibration configured"))?;
+    let mut samples = Vec::new();
+    for (x, y) in config.targets() {
+        let capture = capture_at(x, y, config.capture_z).await?;
+        let limits = LIMITS.lock().await.clone().expect("init must be called");
+        let carriage = limits.carriage_xy(actuator::CAMERA, x, y)?;
+        let pixel = camera::locate_fiducial(&capture.image)?;
+        log::info!("Fiducial at {pixel:?} px from carriage {carriage:?}");
+        samples.push(Sample {
+            carriage: (carriage.0 as f64, carriage.1 as f64),
+            pixel,
+        });
+    }
+    let fiducial = (config.fiducial_x as f64, config.fiducial_y as f64);
+    let calibration = Calibration::solve(&samples, fiducial, config.capture_z)?;
+    log::info!("Camera calibrated: {calibration}");
+    database::insert_camera_calibration(calibration.to_data(timestamp())).await?;
+
+    let mut ac = ACTUATOR.lock().await;
+    let profile = ac.as_mut().expect("init must be called");
+    let tool = profile
+        .tools
+        .entry(actuator::CAMERA.to_owned())
+        .or_default();
+    *tool = ToolOffset {
+        x: calibration.offset.0.round() as i32,
+        y: calibration.offset.1.round() as i32,
+        z: tool.z,
+    };
+    LIMITS.lock().await.replace(profile.travel_limits());
+    drop(ac);
+    CALIBRATION.lock().await.replace(calibration.clone());
+    sync_profile().await?;
+    flag_unreachable_positions().await?;
+    Ok(calibration)
+}
+
+/// Scans the whole bed in the background, leaving proposals for an admin.
+pub async fn start_survey() -> anyhow::Result<()> {
+    let config = CAMERA
+        .lock()
+        .await
+        .as_ref()
+        .expect("init must be called")
+        .survey
+        .clone();
+    survey::start(config).await
+}
+
+pub async fn resolve_survey_proposal(id: i64, approve: bool) -> anyhow::Result<()> {
+    let capture_z = CAMERA
+        .lock()
+        .await
+        .as_ref()
+        .expect("init must be called")
+        .survey
+        .capture_z;
+    survey::resolve(id, approve, capture_z).await
+}
+
+/// Additive channels that stages can dose besides water.
+pub async fn dosing_channels() -> Vec<String> {
+    ACTUATOR
+        .lock()
+        .await
+        .as_ref()
+        .expect("init must be called")
+        .backend
+        .channels()
 }
 
 pub async fn recheck_id(check_id: i64) -> anyhow::Result<()> {
@@ -177,6 +493,7 @@ pub async fn recheck_id(check_id: i64) -> anyhow::Result<()> {
             water_period: 1000,
             check_period: 1000,
             water_duration: 1,
+            water_volume: None,
         })
         .await
         .map_err(|e| dbg!(e))?
@@ -187,20 +504,11 @@ pub async fn recheck_id(check_id: i64) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()> {
-    let position = database::query_position(Some(position_id), None)
-        .await?
-        .pop();
-    if position.is_none() {
-        return Ok(());
-    }
-    let position = position.unwrap();
-
-    let capture = capture_at(position.x as u32, position.y as u32)
-        .await
-        .map_err(|e| dbg!(e))?;
+/// Captures from a camera position and crops the centred square of the
+/// frame, the part the detector sees.
+async fn capture_cropped(x: u32, y: u32, z: Option<u32>) -> anyhow::Result<(DynamicImage, i64)> {
+    let capture = capture_at(x, y, z).await.map_err(|e| dbg!(e))?;
     let image = capture.image;
-    let created_ts = capture.timestamp;
     let edge = image.height().min(image.width());
 
     let image = image.crop_imm(
@@ -210,7 +518,16 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
         edge,
     );
     //.resize(640, 640, image::imageops::FilterType::Gaussian);
+    Ok((image, capture.timestamp))
+}
 
+/// Captures from a camera position and detects on the cropped frame.
+async fn capture_square(
+    x: u32,
+    y: u32,
+    z: Option<u32>,
+) -> anyhow::Result<(DynamicImage, DetectionResult, i64)> {
+    let (image, timestamp) = capture_cropped(x, y, z).await?;
     let detection = DETECTOR
         .lock()
         .await
@@ -219,6 +536,101 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
         .detect(&image)
         .await
         .map_err(|e| dbg!(e))?;
+    Ok((image, detection, timestamp))
+}
+
+/// Camera position that would bring the detection to the image centre, if
+/// centering is enabled and it is off by more than the tolerance. Drift from
+/// where the position was created is capped.
+async fn centering_target(
+    position: &database::PositionData,
+    edge: u32,
+    detection: &DetectionResult,
+) -> anyhow::Result<Option<(u32, u32)>> {
+    let centering = CAMERA.lock().await.as_ref().unwrap().centering.clone();
+    let Some(centering) = centering else {
+        return Ok(None);
+    };
+    let capture_z = position.capture_z.map(|z| z as u32);
+    let Some(transform) = pixel_transform(capture_z).await else {
+        log::warn!(
+            "Position {} not centered: no pixel size known at its capture height",
+            position.id
+        );
+        return Ok(None);
+    };
+    let (cx, cy) = detection.center();
+    let half = edge as f64 / 2.0;
+    let (dx, dy) = transform.to_mm(cx as f64 - half, cy as f64 - half);
+    if dx.hypot(dy) <= centering.tolerance_mm {
+        return Ok(None);
+    }
+
+    let max = centering.max_drift_mm as i64;
+    let clamp = |current: i64, origin: Option<i64>, delta: f64| {
+        let origin = origin.unwrap_or(current);
+        (current + delta.round() as i64).clamp((origin - max).max(0), origin + max) as u32
+    };
+    let x = clamp(position.x, position.origin_x, dx);
+    let y = clamp(position.y, position.origin_y, dy);
+    if (x as i64, y as i64) == (position.x, position.y) {
+        log::warn!(
+            "Position {} is {dx:.1}, {dy:.1} mm off centre but at its drift limit",
+            position.id
+        );
+        return Ok(None);
+    }
+    if let Some(other) = database::query_position(None, Some((x, y))).await?.pop() {
+        log::warn!(
+            "Position {} not centered onto position {}",
+            position.id,
+            other.id
+        );
+        return Ok(None);
+    }
+    let water_z = position.water_z.map(|z| z as u32);
+    if let Err(e) = validate_position(x, y, capture_z, water_z).await {
+        log::warn!("Position {} not centered: {e}", position.id);
+        return Ok(None);
+    }
+    Ok(Some((x, y)))
+}
+
+pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()> {
+    let position = database::query_position(Some(position_id), None)
+        .await?
+        .pop();
+    if position.is_none() {
+        return Ok(());
+    }
+    let mut position = position.unwrap();
+
+    let capture_z = position.capture_z.map(|z| z as u32);
+    let (mut image, mut detection, mut created_ts) =
+        capture_square(position.x as u32, position.y as u32, capture_z).await?;
+    if let Some((x, y)) = centering_target(&position, image.width(), &detection).await? {
+        log::info!(
+            "Centering position {} from ({}, {}) to ({x}, {y})",
+            position.id,
+            position.x,
+            position.y
+        );
+        (image, detection, created_ts) = capture_square(x, y, capture_z).await?;
+        database::update_position_xy(position.id, x, y).await?;
+        (position.x, position.y) = (x as i64, y as i64);
+    }
+    let edge = image.width();
+    let area = pixel_transform(capture_z).await.map(|transform| {
+        let (cx, cy) = detection.center();
+        let (x, y) = transform.to_mm(cx as f64 - edge as f64 / 2.0, cy as f64 - edge as f64 / 2.0);
+        let (width, height) = transform.extent(detection.width as f64, detection.height as f64);
+        SprayArea {
+            x,
+            y,
+            width,
+            height,
+        }
+    });
 
     let image = imageproc::drawing::draw_hollow_rect(
         &image.to_rgb8(),
@@ -255,6 +667,7 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
             water_period: 1000,
             check_period: 1000,
             water_duration: 1,
+            water_volume: None,
         })
         .await?
     };
@@ -266,6 +679,8 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
         stage_id: stage.id,
         image_id,
         watered: false,
+        water_ml: None,
+        water_note: None,
     })
     .await?;
 
@@ -275,13 +690,62 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
     match last_water {
         Some(last_water)
             if (last_water.created_ts + stage.water_period > timestamp()) && !force_water => {}
+        _ if water_lockout().await?.is_some() => {
+            log::warn!("Position {} not watered: reservoir empty", position.id);
+            database::upsert_check(database::CheckData {
+                id: check_id,
+                position_id: position.id,
+                created_ts,
+                stage_id: stage.id,
+                image_id,
+                watered: false,
+                water_ml: None,
+                water_note: Some("water skipped: reservoir empty".to_owned()),
+            })
+            .await?;
+        }
         _ => {
-            water_at(
+            let mut stage_doses: Vec<_> = database::query_stage_doses(Some(stage.id))
+                .await?
+                .into_iter()
+                .map(|dose| {
+                    let max = Duration::from_secs(dose.max_duration as u64);
+                    let ml = dose.amount_ml;
+                    (dose.channel, Some(ml), Dose::Volume { ml, max })
+                })
+                .collect();
+            // Additives go first so that the water flushes them in.
+            let max = Duration::from_secs(stage.water_duration as u64);
+            stage_doses.push((
+                WATER.to_owned(),
+                stage.water_volume,
+                match stage.water_volume {
+                    Some(ml) => Dose::Volume { ml, max },
+                    None => Dose::Duration(max),
+                },
+            ));
+            let doses: Vec<_> = stage_doses
+                .iter()
+                .map(|(channel, _, dose)| (channel.clone(), *dose))
+                .collect();
+            let delivered = water_at(
                 position.x as u32,
                 position.y as u32,
-                Duration::from_secs(stage.water_duration as u64),
+                position.water_z.map(|z| z as u32),
+                area,
+                &doses,
             )
             .await?;
+            for ((channel, amount_ml, _), delivered_ml) in stage_doses.into_iter().zip(&delivered) {
+                database::insert_check_dose(database::CheckDoseData {
+                    check_id,
+                    channel,
+                    amount_ml,
+                    delivered_ml: *delivered_ml,
+                })
+                .await?;
+            }
+            let water_ml = delivered.last().copied().flatten();
             database::upsert_check(database::CheckData {
                 id: check_id,
                 position_id: position.id,
@@ -289,8 +753,14 @@ pub async fn check_at(position_id: i64, force_water: bool) -> anyhow::Result<()>
                 stage_id: stage.id,
                 image_id,
                 watered: true,
+                water_ml,
+                water_note: None,
             })
             .await?;
+            let reservoir = RESERVOIR.lock().await.clone();
+            if let (Some(reservoir), Some(ml)) = (reservoir, water_ml) {
+                reservoir.dispensed(ml).await?;
+            }
         }
     }
 
@@ -304,13 +774,17 @@ pub async fn start_automation() {
             if false {
                 break;
             }
+            if is_halted() {
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
 
             let positions = database::query_position(None, None).await?;
             log::info!("iterating {} positions", positions.len());
 
             let mut handlers = Vec::new();
 
-            for pos in positions {
+            for pos in positions.into_iter().filter(|pos| !pos.unreachable) {
                 let handler = async_std::task::spawn(async move {
                     let last_check = database::query_last_checks(Some(pos.id), false)
                         .await?
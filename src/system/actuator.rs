@@ -1,41 +1,611 @@
-mod gpio_pin;
+pub mod estop;
+mod flow;
+pub mod gpio_pin;
+mod grbl;
+mod idle;
+mod limits;
 mod linear;
+mod planner;
+mod pulse;
+mod simulator;
+mod spray;
+mod stepper;
+mod tool;
+pub mod watchdog;
 mod watering;
 
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use derive_getters::Getters;
-use futures::future::join;
 use serde::{Deserialize, Serialize};
 
-use {gpio_pin::LocalGpioConfig, linear::LocalLinearConfig, watering::WateringConfig};
+pub use limits::{KeepOutZone, MoveError, TravelLimits};
+pub use spray::SprayArea;
+pub use tool::{ToolOffset, CAMERA, NOZZLE};
+use {
+    estop::EstopConfig, gpio_pin::LocalGpioConfig, grbl::GrblConfig, simulator::SimulatorConfig,
+    spray::SprayConfig, stepper::GpioStepperConfig,
+};
+
+/// Channel of the main watering pump, the others being additives such as a
+/// nutrient mix.
+pub const WATER: &str = "water";
+
+/// How much to water: for a fixed time, or until `ml` millilitres have been
+/// measured, giving up after `max`.
+#[derive(Debug, Clone, Copy)]
+pub enum Dose {
+    Duration(Duration),
+    Volume { ml: f64, max: Duration },
+}
+
+impl Dose {
+    /// The first `k` of `n` equal shares, for doses spread over a spray
+    /// pattern.
+    fn shares(self, k: usize, n: usize) -> Dose {
+        let (k, n) = (k as u32, n.max(1) as u32);
+        match self {
+            Dose::Duration(dur) => Dose::Duration(dur * k / n),
+            Dose::Volume { ml, max } => Dose::Volume {
+                ml: ml * k as f64 / n as f64,
+                max: max * k / n,
+            },
+        }
+    }
+}
+
+/// Hardware-specific part of the gantry: how moves and watering are carried out.
+pub trait MotionBackend {
+    async fn home(&mut self) -> anyhow::Result<()>;
+    async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()>;
+    /// Moves the optional Z axis; 0 is fully raised.
+    async fn goto_z(&mut self, z: u32) -> anyhow::Result<()>;
+    /// Switches the pump of `channel` on and leaves it running, so that the
+    /// nozzle can sweep while it pumps.
+    async fn start_pump(&mut self, channel: &str) -> anyhow::Result<()>;
+    /// Keeps the running pump on until `dose` has been delivered since it
+    /// was started.
+    async fn pump_until(&mut self, channel: &str, dose: Dose) -> anyhow::Result<()>;
+    /// Switches the pump off and returns the volume delivered since it was
+    /// started in ml when it is measured or calibrated.
+    async fn stop_pump(&mut self, channel: &str) -> anyhow::Result<Option<f64>>;
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionBackendConfig {
+    Gpio(Box<GpioStepperConfig>),
+    Grbl(GrblConfig),
+    Simulator(SimulatorConfig),
+}
+
+/// Configurations written before the backend table was introduced put the
+/// GPIO gantry straight under `[actuators]`; they are read as
+/// `[actuators.gpio]` and saved in that form by the next profile update.
+impl<'de> Deserialize<'de> for MotionBackendConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        enum Tagged {
+            Gpio(Box<GpioStepperConfig>),
+            Grbl(GrblConfig),
+            Simulator(SimulatorConfig),
+        }
+
+        let table = toml::Table::deserialize(deserializer)?;
+        let tagged = ["gpio", "grbl", "simulator"]
+            .iter()
+            .any(|backend| table.contains_key(*backend));
+        if !tagged {
+            log::warn!("No backend table under [actuators], reading it as [actuators.gpio]");
+            return toml::Value::Table(table)
+                .try_into()
+                .map(MotionBackendConfig::Gpio)
+                .map_err(serde::de::Error::custom);
+        }
+        let backend = toml::Value::Table(table)
+            .try_into()
+            .map_err(serde::de::Error::custom)?;
+        Ok(match backend {
+            Tagged::Gpio(backend) => MotionBackendConfig::Gpio(backend),
+            Tagged::Grbl(backend) => MotionBackendConfig::Grbl(backend),
+            Tagged::Simulator(backend) => MotionBackendConfig::Simulator(backend),
+        })
+    }
+}
+
+impl Default for MotionBackendConfig {
+    fn default() -> Self {
+        MotionBackendConfig::Gpio(Box::default())
+    }
+}
+
+impl MotionBackendConfig {
+    /// GPIO outputs and the level that makes them safe: pump off, drivers
+    /// disabled.
+    pub fn safe_outputs(&self) -> Vec<(LocalGpioConfig, bool)> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => {
+                let mut outputs: Vec<_> = self
+                    .pump_outputs()
+                    .into_iter()
+                    .map(|pin| (pin, false))
+                    .collect();
+                outputs.push((backend.en_pin.clone(), true));
+                outputs
+            }
+            MotionBackendConfig::Grbl(_) | MotionBackendConfig::Simulator(_) => Vec::new(),
+        }
+    }
+
+    /// GPIO outputs driving a pump or valve, which must never be left on.
+    pub fn pump_outputs(&self) -> Vec<LocalGpioConfig> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => std::iter::once(&backend.watering)
+                .chain(backend.dosing.values())
+                .map(|channel| channel.pin.clone())
+                .collect(),
+            MotionBackendConfig::Grbl(_) | MotionBackendConfig::Simulator(_) => Vec::new(),
+        }
+    }
+
+    /// Every GPIO line the backend drives or reads, named after its role.
+    pub fn gpio_lines(&self) -> Vec<(String, LocalGpioConfig)> {
+        let MotionBackendConfig::Gpio(backend) = self else {
+            return Vec::new();
+        };
+        let mut lines = vec![("en".to_owned(), backend.en_pin.clone())];
+        lines.extend(backend.linear_x.gpio_lines("linear_x"));
+        lines.extend(backend.linear_y.gpio_lines("linear_y"));
+        if let Some(linear_z) = &backend.linear_z {
+            lines.extend(linear_z.gpio_lines("linear_z"));
+        }
+        lines.extend(backend.watering.gpio_lines("watering"));
+        for (name, channel) in &backend.dosing {
+            lines.extend(channel.gpio_lines(&format!("dosing:{name}")));
+        }
+        lines
+    }
+
+    /// Soft limit of the X and Y axes in mm.
+    pub fn travel(&self) -> (u32, u32) {
+        match self {
+            MotionBackendConfig::Gpio(backend) => {
+                (backend.linear_x.axis.limit, backend.linear_y.axis.limit)
+            }
+            MotionBackendConfig::Grbl(backend) => (backend.x_limit, backend.y_limit),
+            MotionBackendConfig::Simulator(backend) => {
+                (backend.linear_x.limit, backend.linear_y.limit)
+            }
+        }
+    }
+
+    /// Soft limit of the Z axis in mm, `None` without one.
+    pub fn z_travel(&self) -> Option<u32> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend
+                .linear_z
+                .as_ref()
+                .map(|linear_z| linear_z.axis.limit),
+            MotionBackendConfig::Grbl(backend) => backend.z_limit,
+            MotionBackendConfig::Simulator(backend) => {
+                backend.linear_z.as_ref().map(|linear_z| linear_z.limit)
+            }
+        }
+    }
+
+    /// Additive dosing channels besides [`WATER`].
+    pub fn channels(&self) -> Vec<String> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.dosing.keys().cloned().collect(),
+            MotionBackendConfig::Grbl(backend) => backend.dosing.keys().cloned().collect(),
+            MotionBackendConfig::Simulator(backend) => backend.dosing.keys().cloned().collect(),
+        }
+    }
+
+    /// Whether `channel` can dose by volume, with a flow meter or a
+    /// calibrated pump rate.
+    pub fn measures_flow(&self, channel: &str) -> bool {
+        match self {
+            MotionBackendConfig::Gpio(backend) if channel == WATER => {
+                backend.watering.measures_flow()
+            }
+            MotionBackendConfig::Gpio(backend) => backend
+                .dosing
+                .get(channel)
+                .is_some_and(|channel| channel.measures_flow()),
+            MotionBackendConfig::Grbl(_) if channel == WATER => false,
+            MotionBackendConfig::Grbl(backend) => backend
+                .dosing
+                .get(channel)
+                .is_some_and(|channel| channel.ml_per_s.is_some()),
+            MotionBackendConfig::Simulator(backend) if channel == WATER => {
+                backend.flow_ml_per_s.is_some()
+            }
+            MotionBackendConfig::Simulator(backend) => backend.dosing.contains_key(channel),
+        }
+    }
+
+    /// Intermediate carriage position the backend passes through on the way
+    /// to `(x, y)`, to take up backlash.
+    pub fn approach_via(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.approach_via(x, y),
+            MotionBackendConfig::Grbl(_) | MotionBackendConfig::Simulator(_) => None,
+        }
+    }
+
+    /// Where the carriage is, if it can be trusted.
+    pub fn position(&self) -> Option<(u32, u32)> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => (backend.linear_x.position_known()
+                && backend.linear_y.position_known())
+            .then_some((
+                backend.linear_x.axis.position,
+                backend.linear_y.axis.position,
+            )),
+            MotionBackendConfig::Grbl(backend) => *backend.position(),
+            MotionBackendConfig::Simulator(backend) => {
+                Some((backend.linear_x.position, backend.linear_y.position))
+            }
+        }
+    }
+}
+
+impl MotionBackend for MotionBackendConfig {
+    async fn home(&mut self) -> anyhow::Result<()> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.home().await,
+            MotionBackendConfig::Grbl(backend) => backend.home().await,
+            MotionBackendConfig::Simulator(backend) => backend.home().await,
+        }
+    }
+
+    async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.goto(x, y).await,
+            MotionBackendConfig::Grbl(backend) => backend.goto(x, y).await,
+            MotionBackendConfig::Simulator(backend) => backend.goto(x, y).await,
+        }
+    }
+
+    async fn goto_z(&mut self, z: u32) -> anyhow::Result<()> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.goto_z(z).await,
+            MotionBackendConfig::Grbl(backend) => backend.goto_z(z).await,
+            MotionBackendConfig::Simulator(backend) => backend.goto_z(z).await,
+        }
+    }
+
+    async fn start_pump(&mut self, channel: &str) -> anyhow::Result<()> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.start_pump(channel).await,
+            MotionBackendConfig::Grbl(backend) => backend.start_pump(channel).await,
+            MotionBackendConfig::Simulator(backend) => backend.start_pump(channel).await,
+        }
+    }
+
+    async fn pump_until(&mut self, channel: &str, dose: Dose) -> anyhow::Result<()> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.pump_until(channel, dose).await,
+            MotionBackendConfig::Grbl(backend) => backend.pump_until(channel, dose).await,
+            MotionBackendConfig::Simulator(backend) => backend.pump_until(channel, dose).await,
+        }
+    }
+
+    async fn stop_pump(&mut self, channel: &str) -> anyhow::Result<Option<f64>> {
+        match self {
+            MotionBackendConfig::Gpio(backend) => backend.stop_pump(channel).await,
+            MotionBackendConfig::Grbl(backend) => backend.stop_pump(channel).await,
+            MotionBackendConfig::Simulator(backend) => backend.stop_pump(channel).await,
+        }
+    }
+}
 
 #[derive(Getters, Serialize, Deserialize, Clone, Default)]
 pub struct ActuatorProfile {
-    pub en_pin: LocalGpioConfig,
-    pub linear_x: LocalLinearConfig,
-    pub linear_y: LocalLinearConfig,
-    pub watering: WateringConfig,
+    #[serde(flatten)]
+    pub backend: MotionBackendConfig,
+    #[serde(default)]
+    pub estop: Option<EstopConfig>,
+    #[serde(default)]
+    pub keep_out: Vec<KeepOutZone>,
+    /// Offsets of the camera, the nozzle and any other tool, by name.
+    #[serde(default)]
+    pub tools: BTreeMap<String, ToolOffset>,
+    #[serde(default)]
+    pub spray: SprayConfig,
 }
 
 impl ActuatorProfile {
-    pub async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
-        log::info!("Moving");
-        if x != 0 || y != 0 {
-            self.en_pin.set_low()?;
+    /// Switches every pump off and keeps it that way on shutdown, on panic
+    /// and when one outlives its maximum on-time.
+    pub fn guard_pumps(&self) {
+        watchdog::install(self.backend.pump_outputs());
+    }
+
+    /// Every GPIO line of the gantry, the emergency stop included.
+    pub fn gpio_lines(&self) -> Vec<(String, LocalGpioConfig)> {
+        let mut lines = self.backend.gpio_lines();
+        if let Some(estop) = &self.estop {
+            lines.push(("estop".to_owned(), estop.pin.clone()));
         }
-        let (_, _) = join(self.linear_x.goto(x), self.linear_y.goto(y)).await;
+        lines
+    }
 
-        if x == 0 && y == 0 {
-            self.en_pin.set_high()?;
+    /// Checks the move to the carriage position `(x, y)`, including any
+    /// detour the backend takes to approach it.
+    fn validate_move(&self, limits: &TravelLimits, x: u32, y: u32) -> Result<(), MoveError> {
+        let from = self.backend.position();
+        match self.backend.approach_via(x, y) {
+            Some((via_x, via_y)) => {
+                limits.validate(from, via_x, via_y)?;
+                limits.validate(Some((via_x, via_y)), x, y)
+            }
+            None => limits.validate(from, x, y),
+        }
+    }
+
+    pub fn travel_limits(&self) -> TravelLimits {
+        let (x_limit, y_limit) = self.backend.travel();
+        TravelLimits {
+            x_limit,
+            y_limit,
+            z_limit: self.backend.z_travel(),
+            keep_out: self.keep_out.clone(),
+            tools: self.tools.clone(),
+        }
+    }
+
+    /// Brings `tool` over `(x, y)` with the Z axis, if any, raised, then
+    /// lowers it to `z`.
+    pub async fn goto(&mut self, tool: &str, x: u32, y: u32, z: Option<u32>) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        let limits = self.travel_limits();
+        let (x, y) = limits.carriage_xy(tool, x, y)?;
+        self.validate_move(&limits, x, y)?;
+        let z = z.map(|z| limits.carriage_z(tool, z)).transpose()?;
+        if let Some(z) = z {
+            limits.validate_z(z)?;
+        }
+        log::info!("Moving");
+        if limits.z_limit.is_some() {
+            self.backend.goto_z(0).await?;
+        }
+        self.backend.goto(x, y).await?;
+        if let Some(z) = z {
+            self.backend.goto_z(z).await?;
         }
         log::info!("Moving done");
         Ok(())
     }
 
-    pub async fn water_at(&mut self, x: u32, y: u32, dur: Duration) -> anyhow::Result<()> {
-        self.goto(x, y).await?;
-        self.watering.water(dur).await?;
+    pub async fn home(&mut self) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        log::info!("Homing");
+        self.backend.home().await?;
+        log::info!("Homing done");
+        Ok(())
+    }
+
+    /// Moves the carriage at its current height, for sweeps where raising
+    /// the Z axis between waypoints would waste time.
+    async fn sweep_to(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        let limits = self.travel_limits();
+        let (x, y) = limits.carriage_xy(NOZZLE, x, y)?;
+        self.validate_move(&limits, x, y)?;
+        self.backend.goto(x, y).await
+    }
+
+    /// Nozzle stops covering `area` around `(x, y)` with the configured spray
+    /// pattern. Stops out of reach are dropped, leaving at least `(x, y)`.
+    fn spray_stops(
+        &self,
+        x: u32,
+        y: u32,
+        z: Option<u32>,
+        area: Option<SprayArea>,
+    ) -> Vec<(u32, u32)> {
+        let Some(area) = area else {
+            return vec![(x, y)];
+        };
+        let limits = self.travel_limits();
+        let stops: Vec<_> = self
+            .spray
+            .waypoints(&area)
+            .into_iter()
+            .filter_map(|(dx, dy)| {
+                let x = u32::try_from((x as f64 + dx).round() as i64).ok()?;
+                let y = u32::try_from((y as f64 + dy).round() as i64).ok()?;
+                match limits.validate_tool(NOZZLE, x, y, z) {
+                    Ok(()) => Some((x, y)),
+                    Err(e) => {
+                        log::warn!("Spray stop skipped: {e}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        if stops.is_empty() {
+            return vec![(x, y)];
+        }
+        stops
+    }
+
+    /// Doses each channel in turn through the nozzle and returns what each
+    /// delivered. With a detected `area` the nozzle sweeps the stops of the
+    /// spray pattern while the pump runs, each stop getting an equal share of
+    /// the dose, and the next channel sweeps them back. Without a flow meter
+    /// a volume of water falls back to pumping for its maximum duration,
+    /// while other channels are skipped rather than risk an overdose.
+    pub async fn water_at(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: Option<u32>,
+        area: Option<SprayArea>,
+        doses: &[(String, Dose)],
+    ) -> anyhow::Result<Vec<Option<f64>>> {
+        let doses: Vec<_> = doses
+            .iter()
+            .map(|(channel, dose)| match *dose {
+                Dose::Volume { ml, max } if !self.backend.measures_flow(channel) => {
+                    if channel != WATER {
+                        log::error!("Channel {channel} cannot measure {ml} ml, skipped");
+                        return (channel, None);
+                    }
+                    log::warn!("No flow meter to measure {ml} ml, watering for {max:?}");
+                    (channel, Some(Dose::Duration(max)))
+                }
+                dose => (channel, Some(dose)),
+            })
+            .collect();
+
+        let mut route = self.spray_stops(x, y, z, area);
+        let (x, y) = route[0];
+        self.goto(NOZZLE, x, y, z).await?;
+        let mut delivered = vec![None; doses.len()];
+        for ((channel, dose), total) in doses.iter().zip(&mut delivered) {
+            let Some(dose) = *dose else {
+                continue;
+            };
+            self.backend.start_pump(channel).await?;
+            let res = self.sweep(&route, channel, dose).await;
+            let ml = self.backend.stop_pump(channel).await;
+            res?;
+            *total = ml?;
+            route.reverse();
+        }
+        Ok(delivered)
+    }
+
+    /// Moves the running pump's nozzle through `route`, which starts where
+    /// it is, pumping until each stop has had its share of `dose`.
+    async fn sweep(
+        &mut self,
+        route: &[(u32, u32)],
+        channel: &str,
+        dose: Dose,
+    ) -> anyhow::Result<()> {
+        for (i, &(x, y)) in route.iter().enumerate() {
+            if i > 0 {
+                self.sweep_to(x, y).await?;
+            }
+            self.backend
+                .pump_until(channel, dose.shares(i + 1, route.len()))
+                .await?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::simulator::SimEventKind;
+    use super::spray::SprayPattern;
+    use super::*;
+
+    #[test]
+    fn legacy_layout_reads_as_gpio() {
+        let profile: ActuatorProfile = toml::from_str(
+            r#"
+            [en_pin]
+            chip = "stub"
+            line = 0
+
+            [linear_x]
+            reverse = false
+            limit = 200
+            position = 0
+            min_mm_per_s = 10
+            max_mm_per_s = 50
+            step_per_mm = 40
+            step_pin = { chip = "stub", line = 1 }
+            dir_pin = { chip = "stub", line = 2 }
+
+            [linear_y]
+            reverse = false
+            limit = 300
+            position = 0
+            min_mm_per_s = 10
+            max_mm_per_s = 50
+            step_per_mm = 40
+            step_pin = { chip = "stub", line = 3 }
+            dir_pin = { chip = "stub", line = 4 }
+
+            [watering.pin]
+            chip = "stub"
+            line = 5
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(profile.backend, MotionBackendConfig::Gpio(_)));
+        assert_eq!(profile.backend.travel(), (200, 300));
+
+        let saved = toml::to_string(&profile).unwrap();
+        assert!(saved.contains("[gpio.linear_x]"));
+    }
+
+    #[test]
+    fn water_at_sweeps_the_spray_stops_while_pumping() {
+        let mut simulator = SimulatorConfig::default();
+        simulator.realtime = false;
+        simulator.flow_ml_per_s = Some(10.0);
+        simulator.dosing.insert("nutrient".to_owned(), 2.0);
+        let mut profile = ActuatorProfile {
+            backend: MotionBackendConfig::Simulator(simulator),
+            spray: SprayConfig {
+                pattern: SprayPattern::Raster,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let area = SprayArea {
+            width: 20.0,
+            ..Default::default()
+        };
+        let doses = [
+            (WATER.to_owned(), Dose::Duration(Duration::from_secs(3))),
+            (
+                "nutrient".to_owned(),
+                Dose::Duration(Duration::from_secs(3)),
+            ),
+        ];
+        let delivered =
+            async_std::task::block_on(profile.water_at(100, 100, None, Some(area), &doses))
+                .unwrap();
+        assert_eq!(delivered, vec![Some(30.0), Some(6.0)]);
+
+        let MotionBackendConfig::Simulator(sim) = &profile.backend else {
+            unreachable!();
+        };
+        let events: Vec<_> = sim
+            .log()
+            .iter()
+            .filter(|event| !matches!(event.kind, SimEventKind::Step { .. }))
+            .map(|event| event.kind.clone())
+            .collect();
+        let pump = |channel: &str, on| SimEventKind::Pump {
+            channel: channel.to_owned(),
+            on,
+        };
+        // One pump cycle per channel while the nozzle sweeps the pattern,
+        // the second sweeping it back.
+        assert_eq!(
+            events,
+            vec![
+                SimEventKind::Arrived { x: 90, y: 100 },
+                pump(WATER, true),
+                SimEventKind::Arrived { x: 100, y: 100 },
+                SimEventKind::Arrived { x: 110, y: 100 },
+                pump(WATER, false),
+                pump("nutrient", true),
+                SimEventKind::Arrived { x: 100, y: 100 },
+                SimEventKind::Arrived { x: 90, y: 100 },
+                pump("nutrient", false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use async_std::task::sleep;
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::gpio_pin::LocalGpioConfig;
+
+/// Latched once triggered; only [`reset`] clears it.
+static HALTED: AtomicBool = AtomicBool::new(false);
+/// Outputs forced to their safe level on trigger, with that level
+/// (`true` for high).
+static SAFE_OUTPUTS: Mutex<Vec<(LocalGpioConfig, bool)>> = Mutex::new(Vec::new());
+static BUTTON: Mutex<Option<EstopConfig>> = Mutex::new(None);
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EstopConfig {
+    pub pin: LocalGpioConfig,
+    pub active_low: bool,
+}
+
+impl EstopConfig {
+    pub fn pressed(&mut self) -> anyhow::Result<bool> {
+        Ok(self.pin.is_high()? ^ self.active_low)
+    }
+}
+
+pub fn is_halted() -> bool {
+    HALTED.load(Ordering::SeqCst)
+}
+
+pub fn ensure_running() -> anyhow::Result<()> {
+    if is_halted() {
+        bail!("system halted by emergency stop");
+    }
+    Ok(())
+}
+
+pub fn set_safe_outputs(outputs: Vec<(LocalGpioConfig, bool)>) {
+    *SAFE_OUTPUTS.lock().unwrap() = outputs;
+}
+
+pub fn trigger(reason: &str) {
+    HALTED.store(true, Ordering::SeqCst);
+    log::error!("Emergency stop: {reason}");
+    for (pin, high) in SAFE_OUTPUTS.lock().unwrap().iter_mut() {
+        let res = if *high { pin.set_high() } else { pin.set_low() };
+        if let Err(e) = res {
+            log::error!("Unable to force {:?} to safe state: {e}", pin);
+        }
+    }
+}
+
+pub fn reset() -> anyhow::Result<()> {
+    if let Some(button) = BUTTON.lock().unwrap().as_mut() {
+        if button.pressed()? {
+            bail!("emergency stop button still pressed");
+        }
+    }
+    HALTED.store(false, Ordering::SeqCst);
+    log::warn!("Emergency stop reset");
+    Ok(())
+}
+
+/// Polls the e-stop button on its own thread so that it works while the
+/// async executor is busy. A button on a `stub` line, which always reads
+/// high, is ignored.
+pub fn watch(button: Option<EstopConfig>) {
+    let Some(button) = button else {
+        return;
+    };
+    if button.pin.chip == Path::new("stub") {
+        log::warn!("Emergency stop button on a stub line, ignored");
+        return;
+    }
+    BUTTON.lock().unwrap().replace(button.clone());
+    let mut button = button;
+    thread::Builder::new()
+        .name("estop".to_owned())
+        .spawn(move || loop {
+            match button.pressed() {
+                Ok(true) if !is_halted() => trigger("button pressed"),
+                Ok(_) => (),
+                Err(e) => {
+                    if !is_halted() {
+                        trigger(&format!("unable to read button: {e}"));
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        })
+        .expect("unable to spawn e-stop thread");
+}
+
+/// Sleeps for `dur` but returns early with an error once halted.
+pub async fn sleep_unless_halted(dur: Duration) -> anyhow::Result<()> {
+    let deadline = Instant::now() + dur;
+    loop {
+        ensure_running()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        sleep((deadline - now).min(Duration::from_millis(10))).await;
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use derive_getters::Getters;
+use gpio_cdev::{EventRequestFlags, LineRequestFlags};
+use serde::{Deserialize, Serialize};
+
+use super::gpio_pin::{self, LocalGpioConfig};
+
+type Counters = Mutex<BTreeMap<(PathBuf, u32), Arc<AtomicU64>>>;
+static COUNTERS: Counters = Mutex::new(BTreeMap::new());
+
+/// Hall-effect flow sensor emitting one pulse per fixed volume of water.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct FlowMeterConfig {
+    pub pin: LocalGpioConfig,
+    pub pulses_per_ml: f64,
+}
+
+impl Default for FlowMeterConfig {
+    fn default() -> Self {
+        Self {
+            pin: Default::default(),
+            // YF-S201 style sensors give about 450 pulses per litre.
+            pulses_per_ml: 0.45,
+        }
+    }
+}
+
+impl FlowMeterConfig {
+    /// Rising edges counted since the first call, on a thread blocking on
+    /// the line events. A `stub` chip never counts.
+    fn counter(&self) -> anyhow::Result<Arc<AtomicU64>> {
+        let mut counters = COUNTERS.lock().unwrap();
+        let key = (self.pin.chip.clone(), self.pin.line);
+        if let Some(counter) = counters.get(&key) {
+            return Ok(counter.clone());
+        }
+
+        let counter = Arc::new(AtomicU64::new(0));
+        if self.pin.chip != Path::new("stub") {
+            let events = gpio_cdev::Chip::new(&self.pin.chip)?
+                .get_line(self.pin.line)?
+                .events(
+                    LineRequestFlags::INPUT,
+                    EventRequestFlags::RISING_EDGE,
+                    &gpio_pin::consumer(&self.pin),
+                )?;
+            let count = counter.clone();
+            thread::Builder::new()
+                .name("flow-meter".to_owned())
+                .spawn(move || {
+                    for event in events {
+                        if let Err(e) = event {
+                            log::error!("Flow meter stopped counting: {e}");
+                            break;
+                        }
+                        count.fetch_add(1, Ordering::SeqCst);
+                    }
+                })?;
+        }
+        counters.insert(key, counter.clone());
+        Ok(counter)
+    }
+
+    pub fn pulses(&self) -> anyhow::Result<u64> {
+        Ok(self.counter()?.load(Ordering::SeqCst))
+    }
+
+    pub fn ml(&self, pulses: u64) -> f64 {
+        pulses as f64 / self.pulses_per_ml.max(f64::EPSILON)
+    }
+}
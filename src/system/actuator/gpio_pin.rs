@@ -1,17 +1,66 @@
 use std::{
     collections::BTreeMap,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
 };
 
+use anyhow::{anyhow, bail};
+use async_std::channel::{self, Receiver, Sender};
 use derive_getters::Getters;
-use embedded_hal::digital::OutputPin;
-use gpio_cdev::LineRequestFlags;
+use embedded_hal::digital::{InputPin, OutputPin};
+use gpio_cdev::{EventRequestFlags, LineRequestFlags};
 use linux_embedded_hal::CdevPin;
 use serde::{Deserialize, Serialize};
 
 type Gpios = Mutex<BTreeMap<(PathBuf, u32), Arc<Mutex<CdevPin>>>>;
 static GPIOS: Gpios = Mutex::new(BTreeMap::new());
+/// Role of each configured line, shown as its consumer by `gpioinfo`.
+static CONSUMERS: Mutex<BTreeMap<(PathBuf, u32), String>> = Mutex::new(BTreeMap::new());
+/// Inputs from the `[inputs.<name>]` tables of the configuration.
+static INPUTS: Mutex<BTreeMap<String, InputConfig>> = Mutex::new(BTreeMap::new());
+static MONITORS: Mutex<BTreeMap<String, Arc<Monitor>>> = Mutex::new(BTreeMap::new());
+
+/// Records the role of every line, failing when a line is given two roles.
+/// `stub` lines are exempt.
+pub fn claim(lines: Vec<(String, LocalGpioConfig)>) -> anyhow::Result<()> {
+    let mut claimed: BTreeMap<(PathBuf, u32), String> = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for (role, pin) in lines {
+        if pin.chip == Path::new("stub") {
+            continue;
+        }
+        let key = (pin.chip.clone(), pin.line);
+        match claimed.get(&key) {
+            Some(owner) => conflicts.push(format!(
+                "line {} of {} is assigned to both {owner} and {role}",
+                pin.line,
+                pin.chip.display()
+            )),
+            None => {
+                claimed.insert(key, role);
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!("conflicting GPIO configuration: {}", conflicts.join("; "));
+    }
+    *CONSUMERS.lock().unwrap() = claimed;
+    Ok(())
+}
+
+/// Consumer label to request `pin` with, such as `agrivision:linear_x:step`.
+pub(super) fn consumer(pin: &LocalGpioConfig) -> String {
+    match CONSUMERS.lock().unwrap().get(&(pin.chip.clone(), pin.line)) {
+        Some(role) => format!("agrivision:{role}"),
+        None => "agrivision".to_owned(),
+    }
+}
 
 pub fn get_output(pin: &LocalGpioConfig) -> anyhow::Result<Arc<Mutex<CdevPin>>> {
     let mut gpios = GPIOS.lock().unwrap();
@@ -22,7 +71,57 @@ pub fn get_output(pin: &LocalGpioConfig) -> anyhow::Result<Arc<Mutex<CdevPin>>>
         let gpin = CdevPin::new(
             gpio_cdev::Chip::new(pin.chip())?
                 .get_line(*pin.line())?
-                .request(LineRequestFlags::OUTPUT, 0, "my_pin")?,
+                .request(LineRequestFlags::OUTPUT, 0, &consumer(pin))?,
+        )?;
+
+        let gpin = Arc::new(Mutex::new(gpin));
+        gpios.insert((pin.chip.clone(), pin.line), gpin.clone());
+        Ok(gpin)
+    }
+}
+
+/// Output line looked up once, for callers switching it too often to go
+/// through [`GPIOS`] every time. `None` for a `stub` line.
+pub struct Output(Option<Arc<Mutex<CdevPin>>>);
+
+impl Output {
+    pub fn set_high(&self) -> anyhow::Result<()> {
+        if let Some(pin) = &self.0 {
+            pin.lock().unwrap().set_high()?;
+        }
+        Ok(())
+    }
+    pub fn set_low(&self) -> anyhow::Result<()> {
+        if let Some(pin) = &self.0 {
+            pin.lock().unwrap().set_low()?;
+        }
+        Ok(())
+    }
+}
+
+/// Duplicate of the descriptor of the output line `pin`, requesting it first
+/// when needed, so it can be driven without going through [`GPIOS`].
+pub(super) fn output_fd(pin: &LocalGpioConfig) -> anyhow::Result<RawFd> {
+    let pin = get_output(pin)?;
+    let fd = pin.lock().unwrap().0.as_raw_fd();
+    // SAFETY: dup(2) on a descriptor kept open by `pin` for the call.
+    let fd = unsafe { libc::dup(fd) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(fd)
+}
+
+pub fn get_input(pin: &LocalGpioConfig) -> anyhow::Result<Arc<Mutex<CdevPin>>> {
+    let mut gpios = GPIOS.lock().unwrap();
+
+    if let Some(pin) = gpios.get(&(pin.chip.clone(), pin.line)) {
+        Ok(pin.clone())
+    } else {
+        let gpin = CdevPin::new(
+            gpio_cdev::Chip::new(pin.chip())?
+                .get_line(*pin.line())?
+                .request(LineRequestFlags::INPUT, 0, &consumer(pin))?,
         )?;
 
         let gpin = Arc::new(Mutex::new(gpin));
@@ -31,7 +130,7 @@ pub fn get_output(pin: &LocalGpioConfig) -> anyhow::Result<Arc<Mutex<CdevPin>>>
     }
 }
 
-#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct LocalGpioConfig {
     pub chip: PathBuf,
     pub line: u32,
@@ -47,8 +146,15 @@ impl Default for LocalGpioConfig {
 }
 
 impl LocalGpioConfig {
+    /// Requests the line as an output, when needed, and keeps its handle.
+    pub fn output(&self) -> anyhow::Result<Output> {
+        if self.chip == Path::new("stub") {
+            return Ok(Output(None));
+        }
+        Ok(Output(Some(get_output(self)?)))
+    }
     pub fn set_high(&mut self) -> anyhow::Result<()> {
-        if self.chip == PathBuf::from("stub") {
+        if self.chip == Path::new("stub") {
             return Ok(());
         }
         let pin = get_output(self)?;
@@ -56,11 +162,168 @@ impl LocalGpioConfig {
         Ok(())
     }
     pub fn set_low(&mut self) -> anyhow::Result<()> {
-        if self.chip == PathBuf::from("stub") {
+        if self.chip == Path::new("stub") {
             return Ok(());
         }
         let pin = get_output(self)?;
         pin.lock().unwrap().set_low()?;
         Ok(())
     }
+    /// Reads the line level. A `stub` chip always reads high so that
+    /// switches wired on a test rig without hardware look triggered.
+    pub fn is_high(&mut self) -> anyhow::Result<bool> {
+        if self.chip == Path::new("stub") {
+            return Ok(true);
+        }
+        let pin = get_input(self)?;
+        let high = pin.lock().unwrap().is_high()?;
+        Ok(high)
+    }
+}
+
+/// Kernel `GPIOHANDLE_REQUEST_BIAS_*` flags (Linux 5.5 and later), which
+/// gpio-cdev does not name.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bias {
+    /// Keep whatever the board or device tree configured.
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+impl Bias {
+    fn flags(self) -> LineRequestFlags {
+        LineRequestFlags::from_bits_retain(match self {
+            Bias::AsIs => 0,
+            Bias::PullUp => 1 << 5,
+            Bias::PullDown => 1 << 6,
+            Bias::Disabled => 1 << 7,
+        })
+    }
+}
+
+/// Switch, float or button wired to a GPIO line.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InputConfig {
+    pub pin: LocalGpioConfig,
+    #[serde(default)]
+    pub bias: Bias,
+    #[serde(default)]
+    pub active_low: bool,
+    /// How long the level must hold before a change is reported.
+    #[serde(default)]
+    pub debounce_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Activated,
+    Deactivated,
+}
+
+/// Debounced state of an input, kept up to date by a thread blocking on
+/// the line events.
+struct Monitor {
+    active: AtomicBool,
+    subscribers: Mutex<Vec<Sender<Edge>>>,
+}
+
+impl Monitor {
+    fn set(&self, active: bool) {
+        if self.active.swap(active, Ordering::SeqCst) == active {
+            return;
+        }
+        let edge = if active {
+            Edge::Activated
+        } else {
+            Edge::Deactivated
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(edge).is_ok());
+    }
+}
+
+impl InputConfig {
+    /// Starts watching the line. A `stub` chip reads high forever, like
+    /// [`LocalGpioConfig::is_high`].
+    fn monitor(&self) -> anyhow::Result<Arc<Monitor>> {
+        if self.pin.chip == Path::new("stub") {
+            return Ok(Arc::new(Monitor {
+                active: AtomicBool::new(!self.active_low),
+                subscribers: Mutex::new(Vec::new()),
+            }));
+        }
+        let mut flags = LineRequestFlags::INPUT | self.bias.flags();
+        if self.active_low {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+        }
+        let mut events = gpio_cdev::Chip::new(&self.pin.chip)?
+            .get_line(self.pin.line)?
+            .events(flags, EventRequestFlags::BOTH_EDGES, &consumer(&self.pin))?;
+        let monitor = Arc::new(Monitor {
+            active: AtomicBool::new(events.get_value()? == 1),
+            subscribers: Mutex::new(Vec::new()),
+        });
+
+        let debounce = Duration::from_millis(self.debounce_ms);
+        let state = monitor.clone();
+        thread::Builder::new()
+            .name("gpio-input".to_owned())
+            .spawn(move || loop {
+                if let Err(e) = events.get_event() {
+                    log::error!("Input stopped reporting: {e}");
+                    break;
+                }
+                // Bounces queue more events; each one only re-reads the
+                // settled level.
+                thread::sleep(debounce);
+                match events.get_value() {
+                    Ok(value) => state.set(value == 1),
+                    Err(e) => log::error!("Unable to read input: {e}"),
+                }
+            })?;
+        Ok(monitor)
+    }
+}
+
+pub fn register_inputs(inputs: BTreeMap<String, InputConfig>) {
+    MONITORS.lock().unwrap().clear();
+    *INPUTS.lock().unwrap() = inputs;
+}
+
+pub fn inputs() -> BTreeMap<String, InputConfig> {
+    INPUTS.lock().unwrap().clone()
+}
+
+fn monitor(name: &str) -> anyhow::Result<Arc<Monitor>> {
+    let mut monitors = MONITORS.lock().unwrap();
+    if let Some(monitor) = monitors.get(name) {
+        return Ok(monitor.clone());
+    }
+    let input = INPUTS
+        .lock()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| anyhow!("no input named {name}"))?;
+    let monitor = input.monitor()?;
+    monitors.insert(name.to_owned(), monitor.clone());
+    Ok(monitor)
+}
+
+/// Debounced state of the input `name`.
+pub fn is_active(name: &str) -> anyhow::Result<bool> {
+    Ok(monitor(name)?.active.load(Ordering::SeqCst))
+}
+
+/// Stream of the debounced changes of the input `name`, from now on.
+pub fn edges(name: &str) -> anyhow::Result<Receiver<Edge>> {
+    let (sender, receiver) = channel::unbounded();
+    monitor(name)?.subscribers.lock().unwrap().push(sender);
+    Ok(receiver)
 }
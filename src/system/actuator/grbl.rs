@@ -0,0 +1,695 @@
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use async_std::channel::{self, Receiver};
+use async_std::future::timeout;
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+use serialport::TTYPort;
+
+use super::{estop, watchdog, Dose, MotionBackend, WATER};
+
+/// Additive pump switched by its own pair of M-codes, e.g. `M64 P0` and
+/// `M65 P0` on controllers with extra digital outputs.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GrblDoser {
+    pub on: String,
+    pub off: String,
+    /// Calibrated pump rate, needed to dose by volume.
+    #[serde(default)]
+    pub ml_per_s: Option<f64>,
+}
+
+/// Gantry driven by a GRBL-flashed controller over a serial port. Any device
+/// path works, including the slave side of a pty for a fake controller.
+#[derive(Getters, Serialize, Deserialize)]
+pub struct GrblConfig {
+    pub port: PathBuf,
+    pub baud_rate: u32,
+    pub rapid: bool,
+    pub feed_rate: u32,
+    pub pump_on: String,
+    pub pump_off: String,
+    pub homing: bool,
+    pub poll_interval_ms: u64,
+    pub timeout_s: u64,
+    pub x_limit: u32,
+    pub y_limit: u32,
+    /// Soft limit of the Z axis, `None` for machines without one.
+    #[serde(default)]
+    pub z_limit: Option<u32>,
+    #[serde(default)]
+    pub dosing: BTreeMap<String, GrblDoser>,
+    /// Hard limit after which the watchdog soft-resets the controller,
+    /// stopping any pump or coolant left on.
+    #[serde(default = "default_max_on_s")]
+    pub max_on_s: u64,
+    /// Last position confirmed by a status report.
+    #[serde(skip_serializing, skip_deserializing)]
+    position: Option<(u32, u32)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    serial: Option<SerialLink>,
+    /// Last work coordinate offset reported.
+    #[serde(skip_serializing, skip_deserializing)]
+    wco: [f32; 3],
+    /// When the running pump was switched on.
+    #[serde(skip_serializing, skip_deserializing)]
+    pump_started: Option<Instant>,
+}
+
+fn default_max_on_s() -> u64 {
+    300
+}
+
+impl Clone for GrblConfig {
+    fn clone(&self) -> Self {
+        GrblConfig {
+            port: self.port.clone(),
+            baud_rate: self.baud_rate,
+            rapid: self.rapid,
+            feed_rate: self.feed_rate,
+            pump_on: self.pump_on.clone(),
+            pump_off: self.pump_off.clone(),
+            homing: self.homing,
+            poll_interval_ms: self.poll_interval_ms,
+            timeout_s: self.timeout_s,
+            x_limit: self.x_limit,
+            y_limit: self.y_limit,
+            z_limit: self.z_limit,
+            dosing: self.dosing.clone(),
+            max_on_s: self.max_on_s,
+            position: self.position,
+            serial: None,
+            wco: self.wco,
+            pump_started: None,
+        }
+    }
+}
+
+impl Default for GrblConfig {
+    fn default() -> Self {
+        Self {
+            port: "/dev/ttyUSB0".into(),
+            baud_rate: 115200,
+            rapid: false,
+            feed_rate: 3000,
+            pump_on: "M8".to_owned(),
+            pump_off: "M9".to_owned(),
+            homing: true,
+            poll_interval_ms: 100,
+            timeout_s: 120,
+            x_limit: 200,
+            y_limit: 200,
+            z_limit: None,
+            dosing: BTreeMap::new(),
+            max_on_s: default_max_on_s(),
+            position: None,
+            serial: None,
+            wco: [0.0; 3],
+            pump_started: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrblStatus {
+    pub state: String,
+    /// Machine position, reported unless `$10` asks for the work position.
+    pub mpos: Option<[f32; 3]>,
+    pub wpos: Option<[f32; 3]>,
+    /// Work coordinate offset, only included in every few reports.
+    pub wco: Option<[f32; 3]>,
+}
+
+fn parse_axes(axes: &str, line: &str) -> anyhow::Result<[f32; 3]> {
+    let mut axes = axes.split(',').map(str::parse::<f32>);
+    let x = axes
+        .next()
+        .ok_or_else(|| anyhow!("missing x in {line}"))??;
+    let y = axes
+        .next()
+        .ok_or_else(|| anyhow!("missing y in {line}"))??;
+    let z = axes.next().transpose()?.unwrap_or_default();
+    Ok([x, y, z])
+}
+
+impl GrblStatus {
+    /// Parses a report such as `<Idle|MPos:10.000,20.000,0.000|FS:0,0>`.
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let body = line
+            .trim()
+            .strip_prefix('<')
+            .and_then(|l| l.strip_suffix('>'))
+            .ok_or_else(|| anyhow!("not a status report: {line}"))?;
+        let mut fields = body.split('|');
+        let mut status = GrblStatus {
+            state: fields.next().unwrap_or_default().to_owned(),
+            mpos: None,
+            wpos: None,
+            wco: None,
+        };
+        for field in fields {
+            if let Some(axes) = field.strip_prefix("MPos:") {
+                status.mpos = Some(parse_axes(axes, line)?);
+            } else if let Some(axes) = field.strip_prefix("WPos:") {
+                status.wpos = Some(parse_axes(axes, line)?);
+            } else if let Some(axes) = field.strip_prefix("WCO:") {
+                status.wco = Some(parse_axes(axes, line)?);
+            }
+        }
+        if status.mpos.is_none() && status.wpos.is_none() {
+            bail!("status report without position: {line}");
+        }
+        Ok(status)
+    }
+
+    /// Position in the work coordinates G-code moves are given in, using the
+    /// last offset seen when this report has none.
+    pub fn work_position(&self, wco: [f32; 3]) -> [f32; 3] {
+        match (self.wpos, self.mpos) {
+            (Some(wpos), _) => wpos,
+            (None, Some(mpos)) => {
+                let wco = self.wco.unwrap_or(wco);
+                [mpos[0] - wco[0], mpos[1] - wco[1], mpos[2] - wco[2]]
+            }
+            (None, None) => [0.0; 3],
+        }
+    }
+}
+
+/// Open serial port, served by its own thread so that blocking reads and
+/// writes never stall the async executor. Dropping it closes the port.
+struct SerialLink {
+    writes: mpsc::Sender<Vec<u8>>,
+    lines: Receiver<String>,
+    closed: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Waits for the thread to let go of the port, so that a reconnection
+/// does not race it for the controller's answers.
+impl Drop for SerialLink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl SerialLink {
+    fn open(mut port: TTYPort) -> anyhow::Result<Self> {
+        let (writes, pending) = mpsc::channel::<Vec<u8>>();
+        let (received, lines) = channel::unbounded();
+        let closed = Arc::new(AtomicBool::new(false));
+        let closing = closed.clone();
+        let thread = thread::Builder::new()
+            .name("grbl-serial".to_owned())
+            .spawn(move || {
+                let mut rx = Vec::new();
+                let mut buf = [0u8; 64];
+                loop {
+                    loop {
+                        match pending.try_recv() {
+                            Ok(data) => {
+                                if let Err(e) = port.write_all(&data).and_then(|_| port.flush()) {
+                                    log::error!("grbl port write failed: {e}");
+                                    return;
+                                }
+                            }
+                            Err(mpsc::TryRecvError::Empty) => break,
+                            Err(mpsc::TryRecvError::Disconnected) => return,
+                        }
+                    }
+                    if closing.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match port.read(&mut buf) {
+                        Ok(0) => return,
+                        Ok(n) => rx.extend_from_slice(&buf[..n]),
+                        Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                        Err(e) => {
+                            log::error!("grbl port read failed: {e}");
+                            return;
+                        }
+                    }
+                    while let Some(end) = rx.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = rx.drain(..=end).collect();
+                        let line = String::from_utf8_lossy(&line).trim().to_owned();
+                        if !line.is_empty() && received.send_blocking(line).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })?;
+        Ok(SerialLink {
+            writes,
+            lines,
+            closed,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl GrblConfig {
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_s)
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    async fn connect(&mut self) -> anyhow::Result<()> {
+        if self.serial.is_some() {
+            return Ok(());
+        }
+        let port = serialport::new(self.port.to_string_lossy(), self.baud_rate)
+            .timeout(Duration::from_millis(10))
+            .open_native()?;
+        if let Err(e) = watchdog::guard_grbl(&port) {
+            log::error!("GRBL controller is not guarded by the watchdog: {e}");
+        }
+        self.serial.replace(SerialLink::open(port)?);
+
+        // Opening the port resets most Arduino boards; wait for the banner but
+        // carry on without it for controllers that don't reset.
+        self.write("\r\n\r\n")?;
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while let Some(line) = self.read_line(deadline).await? {
+            if line.starts_with("Grbl") {
+                log::info!("Connected to {line}");
+                break;
+            }
+        }
+        if let Some(serial) = &self.serial {
+            while serial.lines.try_recv().is_ok() {}
+        }
+
+        self.command("G21").await?;
+        self.command("G90").await?;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &str) -> anyhow::Result<()> {
+        let serial = self
+            .serial
+            .as_ref()
+            .ok_or_else(|| anyhow!("grbl port not open"))?;
+        if serial.writes.send(data.as_bytes().to_vec()).is_err() {
+            self.serial = None;
+            bail!("grbl port closed");
+        }
+        Ok(())
+    }
+
+    /// Soft-resets the controller, which stops motion and switches the
+    /// M-code outputs off.
+    fn abort(&mut self) {
+        if let Some(serial) = self.serial.take() {
+            serial.writes.send(vec![0x18]).ok();
+        }
+    }
+
+    async fn read_line(&mut self, deadline: Instant) -> anyhow::Result<Option<String>> {
+        loop {
+            if estop::is_halted() {
+                self.abort();
+                bail!("grbl stopped by emergency stop");
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let serial = self
+                .serial
+                .as_ref()
+                .ok_or_else(|| anyhow!("grbl port not open"))?;
+            // Wake up regularly to notice an emergency stop.
+            let wait = (deadline - now).min(Duration::from_millis(10));
+            match timeout(wait, serial.lines.recv()).await {
+                Ok(Ok(line)) => return Ok(Some(line)),
+                Ok(Err(_)) => {
+                    self.serial = None;
+                    bail!("grbl port closed");
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Sends one line of G-code and waits for GRBL to acknowledge it.
+    pub async fn command(&mut self, cmd: &str) -> anyhow::Result<()> {
+        log::debug!("grbl <- {cmd}");
+        self.write(&format!("{cmd}\n"))?;
+        let deadline = Instant::now() + self.timeout();
+        while let Some(line) = self.read_line(deadline).await? {
+            log::debug!("grbl -> {line}");
+            if line == "ok" {
+                return Ok(());
+            } else if line.starts_with("error:") || line.starts_with("ALARM:") {
+                bail!("grbl rejected `{cmd}`: {line}");
+            }
+        }
+        bail!("grbl did not answer `{cmd}`")
+    }
+
+    pub async fn status(&mut self) -> anyhow::Result<GrblStatus> {
+        self.write("?")?;
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while let Some(line) = self.read_line(deadline).await? {
+            if line.starts_with('<') {
+                let status = GrblStatus::parse(&line)?;
+                if let Some(wco) = status.wco {
+                    self.wco = wco;
+                }
+                return Ok(status);
+            } else if line.starts_with("ALARM:") {
+                bail!("grbl alarm: {line}");
+            }
+        }
+        bail!("grbl did not report status")
+    }
+
+    async fn wait_idle(&mut self) -> anyhow::Result<[f32; 3]> {
+        let deadline = Instant::now() + self.timeout();
+        loop {
+            let status = self.status().await?;
+            if status.state == "Idle" {
+                return Ok(status.work_position(self.wco));
+            } else if status.state.starts_with("Alarm") {
+                bail!("grbl in alarm state");
+            } else if Instant::now() >= deadline {
+                bail!("grbl still in {} state", status.state);
+            }
+            if let Err(e) = estop::sleep_unless_halted(self.poll_interval()).await {
+                self.abort();
+                return Err(e);
+            }
+        }
+    }
+
+    /// On and off M-codes of `channel`, with its calibrated rate.
+    fn pump(&self, channel: &str) -> anyhow::Result<(String, String, Option<f64>)> {
+        if channel == WATER {
+            return Ok((self.pump_on.clone(), self.pump_off.clone(), None));
+        }
+        let doser = self
+            .dosing
+            .get(channel)
+            .ok_or_else(|| anyhow!("unknown dosing channel {channel}"))?;
+        Ok((doser.on.clone(), doser.off.clone(), doser.ml_per_s))
+    }
+
+    async fn run(&mut self, cmds: &[&str]) -> anyhow::Result<()> {
+        let res = async {
+            self.connect().await?;
+            for cmd in cmds {
+                self.command(cmd).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if res.is_err() {
+            // Force a fresh handshake on the next call.
+            self.serial = None;
+        }
+        res
+    }
+}
+
+impl MotionBackend for GrblConfig {
+    async fn home(&mut self) -> anyhow::Result<()> {
+        self.position = None;
+        if self.homing {
+            self.run(&["$H"]).await?;
+        }
+        Ok(())
+    }
+
+    async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        let cmd = if self.rapid {
+            format!("G0 X{x} Y{y}")
+        } else {
+            format!("G1 X{x} Y{y} F{}", self.feed_rate)
+        };
+        self.position = None;
+        // `G4 P0` is only acknowledged once the planner buffer is empty.
+        self.run(&[&cmd, "G4 P0"]).await?;
+        let [at_x, at_y, _] = self.wait_idle().await?;
+        if (at_x - x as f32).abs() > 0.5 || (at_y - y as f32).abs() > 0.5 {
+            bail!("grbl stopped at ({at_x}, {at_y}) instead of ({x}, {y})");
+        }
+        self.position = Some((x, y));
+        Ok(())
+    }
+
+    async fn goto_z(&mut self, z: u32) -> anyhow::Result<()> {
+        if self.z_limit.is_none() {
+            bail!("no z axis configured");
+        }
+        // GRBL homes Z at its top and measures downward travel as negative.
+        let cmd = if self.rapid {
+            format!("G0 Z-{z}")
+        } else {
+            format!("G1 Z-{z} F{}", self.feed_rate)
+        };
+        self.run(&[&cmd, "G4 P0"]).await?;
+        let [_, _, at_z] = self.wait_idle().await?;
+        if (at_z + z as f32).abs() > 0.5 {
+            bail!("grbl stopped at z {at_z} instead of -{z}");
+        }
+        Ok(())
+    }
+
+    async fn start_pump(&mut self, channel: &str) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        let (pump_on, _, _) = self.pump(channel)?;
+        // Armed first, so a pump left on by a failed command is still
+        // stopped.
+        watchdog::arm_grbl(Duration::from_secs(self.max_on_s));
+        self.run(&[&pump_on, "G4 P0"]).await?;
+        self.pump_started = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn pump_until(&mut self, channel: &str, dose: Dose) -> anyhow::Result<()> {
+        let Some(since) = self.pump_started else {
+            bail!("pump not running");
+        };
+        let (_, _, rate) = self.pump(channel)?;
+        let dur = match (dose, rate) {
+            (Dose::Duration(dur), _) => dur,
+            (Dose::Volume { ml, max }, Some(rate)) => {
+                Duration::from_secs_f64(ml / rate.max(f64::EPSILON)).min(max)
+            }
+            (Dose::Volume { .. }, None) => bail!("no flow meter configured"),
+        };
+        let wait = (since + dur).saturating_duration_since(Instant::now());
+        if let Err(e) = estop::sleep_unless_halted(wait).await {
+            self.abort();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn stop_pump(&mut self, channel: &str) -> anyhow::Result<Option<f64>> {
+        let (_, pump_off, rate) = self.pump(channel)?;
+        let Some(since) = self.pump_started.take() else {
+            return Ok(None);
+        };
+        let on_for = since.elapsed();
+        self.run(&[&pump_off]).await?;
+        watchdog::disarm_grbl();
+        Ok(rate.map(|rate| rate * on_for.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    use async_std::task::block_on;
+
+    use super::*;
+
+    /// Controller on the master side of a pty. Moves complete at once; the
+    /// status reports machine positions offset by `wco`, which is only
+    /// included in the first report.
+    struct FakeGrbl {
+        wco: [f32; 3],
+        alarm: bool,
+        position: [f32; 3],
+        reports: usize,
+    }
+
+    impl FakeGrbl {
+        fn reply(&mut self, line: &str) -> String {
+            if line.is_empty() {
+                return "Grbl 1.1h ['$' for help]\r\n".to_owned();
+            }
+            if self.alarm {
+                return "ALARM:9\r\n".to_owned();
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("G0" | "G1") => {
+                    for word in words {
+                        let (axis, value) = word.split_at(1);
+                        let Ok(value) = value.parse::<f32>() else {
+                            return "error:2\r\n".to_owned();
+                        };
+                        match axis {
+                            "X" if value > 500.0 => return "error:15\r\n".to_owned(),
+                            "X" => self.position[0] = value,
+                            "Y" => self.position[1] = value,
+                            "Z" => self.position[2] = value,
+                            _ => (),
+                        }
+                    }
+                    "ok\r\n".to_owned()
+                }
+                Some("G21" | "G90" | "G4" | "M8" | "M9" | "$H") => "ok\r\n".to_owned(),
+                _ => "error:20\r\n".to_owned(),
+            }
+        }
+
+        fn status(&mut self) -> String {
+            let [x, y, z] = self.position;
+            let [ox, oy, oz] = self.wco;
+            let state = if self.alarm { "Alarm" } else { "Idle" };
+            let wco = if self.reports == 0 {
+                format!("|WCO:{ox:.3},{oy:.3},{oz:.3}")
+            } else {
+                String::new()
+            };
+            self.reports += 1;
+            format!(
+                "<{state}|MPos:{:.3},{:.3},{:.3}|FS:0,0{wco}>\r\n",
+                x + ox,
+                y + oy,
+                z + oz
+            )
+        }
+    }
+
+    /// Starts `fake` on a new pty and returns a backend talking to it, with
+    /// the slave side kept open for the duration of the test.
+    fn connect(mut fake: FakeGrbl) -> (GrblConfig, OwnedFd) {
+        let (mut master, mut slave) = (-1, -1);
+        let mut name = [0 as libc::c_char; 64];
+        // SAFETY: the descriptors and name buffer outlive the calls.
+        unsafe {
+            assert_eq!(
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                ),
+                0
+            );
+            assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+        }
+        // SAFETY: openpty returned both descriptors, owned from here on.
+        let (mut master, slave) =
+            unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // SAFETY: ptsname_r wrote a NUL-terminated path.
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+
+        thread::spawn(move || {
+            let mut line = String::new();
+            let mut buf = [0u8; 64];
+            while let Ok(n) = master.read(&mut buf) {
+                for &byte in &buf[..n] {
+                    let reply = match byte {
+                        b'?' => fake.status(),
+                        b'\n' => fake.reply(std::mem::take(&mut line).trim()),
+                        b'\r' => continue,
+                        byte => {
+                            line.push(byte as char);
+                            continue;
+                        }
+                    };
+                    if master.write_all(reply.as_bytes()).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        let grbl = GrblConfig {
+            port: path.into(),
+            homing: true,
+            poll_interval_ms: 10,
+            timeout_s: 2,
+            ..Default::default()
+        };
+        (grbl, slave)
+    }
+
+    fn fake(wco: [f32; 3], alarm: bool) -> FakeGrbl {
+        FakeGrbl {
+            wco,
+            alarm,
+            position: [0.0; 3],
+            reports: 0,
+        }
+    }
+
+    #[test]
+    fn status_prefers_work_position() {
+        let status = GrblStatus::parse("<Idle|MPos:15.000,25.000,0.000|WPos:5.000,5.000,0.000>");
+        assert_eq!(status.unwrap().work_position([0.0; 3]), [5.0, 5.0, 0.0]);
+
+        let status =
+            GrblStatus::parse("<Run|MPos:15.000,25.000,-2.000|FS:500,0|WCO:10.000,20.000,0.000>")
+                .unwrap();
+        assert_eq!(status.state, "Run");
+        assert_eq!(status.work_position([0.0; 3]), [5.0, 5.0, -2.0]);
+
+        let status = GrblStatus::parse("<Idle|MPos:15.000,25.000,0.000|FS:0,0>").unwrap();
+        assert_eq!(status.work_position([10.0, 20.0, 0.0]), [5.0, 5.0, 0.0]);
+
+        assert!(GrblStatus::parse("<Idle|FS:0,0>").is_err());
+    }
+
+    #[test]
+    fn goto_checks_arrival_in_work_coordinates() {
+        let (mut grbl, _slave) = connect(fake([10.0, 20.0, 0.0], false));
+        block_on(grbl.goto(50, 60)).unwrap();
+        assert_eq!(*grbl.position(), Some((50, 60)));
+        // Later reports omit the offset, which must be remembered.
+        block_on(grbl.goto(70, 80)).unwrap();
+        assert_eq!(*grbl.position(), Some((70, 80)));
+    }
+
+    #[test]
+    fn error_response_fails_the_command() {
+        let (mut grbl, _slave) = connect(fake([0.0; 3], false));
+        let err = block_on(grbl.goto(600, 0)).unwrap_err();
+        assert!(err.to_string().contains("error:15"), "{err}");
+        assert_eq!(*grbl.position(), None);
+        // The next move reconnects and succeeds.
+        block_on(grbl.goto(100, 0)).unwrap();
+    }
+
+    #[test]
+    fn alarm_fails_homing_and_moves() {
+        let (mut grbl, _slave) = connect(fake([0.0; 3], true));
+        let err = block_on(grbl.home()).unwrap_err();
+        assert!(err.to_string().contains("ALARM:9"), "{err}");
+        assert!(block_on(grbl.goto(10, 10)).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_std::task::sleep;
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::gpio_pin::LocalGpioConfig;
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct IdleConfig {
+    /// Seconds without motion before the drivers are disabled, never if
+    /// unset.
+    pub timeout_s: Option<u64>,
+    /// Delay between enabling the drivers and the first step.
+    pub settle_ms: u64,
+    /// Whether the carriage is known to stay put with the drivers off, e.g.
+    /// with a lead screw. Otherwise homed axes are homed again.
+    pub holds_position: bool,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            timeout_s: None,
+            settle_ms: 100,
+            holds_position: false,
+        }
+    }
+}
+
+struct Drivers {
+    en_pin: LocalGpioConfig,
+    timeout: Option<Duration>,
+    energized: bool,
+    busy: bool,
+    last_used: Instant,
+}
+
+static DRIVERS: Mutex<Option<Drivers>> = Mutex::new(None);
+static WATCHER: Once = Once::new();
+
+fn watch() {
+    thread::Builder::new()
+        .name("idle".to_owned())
+        .spawn(|| loop {
+            if let Some(drivers) = DRIVERS.lock().unwrap().as_mut() {
+                match drivers.timeout {
+                    Some(timeout)
+                        if drivers.energized
+                            && !drivers.busy
+                            && drivers.last_used.elapsed() >= timeout =>
+                    {
+                        match drivers.en_pin.set_high() {
+                            Ok(()) => {
+                                drivers.energized = false;
+                                log::info!("Stepper drivers disabled after {timeout:?} idle");
+                            }
+                            Err(e) => log::error!("Unable to disable stepper drivers: {e}"),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        })
+        .expect("unable to spawn idle thread");
+}
+
+/// Keeps the drivers energized while alive and restarts the idle timeout
+/// when dropped.
+pub struct DriversBusy;
+
+impl Drop for DriversBusy {
+    fn drop(&mut self) {
+        if let Some(drivers) = DRIVERS.lock().unwrap().as_mut() {
+            drivers.busy = false;
+            drivers.last_used = Instant::now();
+        }
+    }
+}
+
+/// Enables the drivers for a move, waiting for them to settle if they were
+/// off. Also returns whether they were off, in which case the carriage may
+/// have moved.
+pub async fn acquire(
+    en_pin: &LocalGpioConfig,
+    config: &IdleConfig,
+) -> anyhow::Result<(DriversBusy, bool)> {
+    WATCHER.call_once(watch);
+    let was_off = {
+        let mut drivers = DRIVERS.lock().unwrap();
+        let drivers = drivers.get_or_insert_with(|| Drivers {
+            en_pin: en_pin.clone(),
+            timeout: None,
+            energized: false,
+            busy: false,
+            last_used: Instant::now(),
+        });
+        drivers.en_pin = en_pin.clone();
+        drivers.timeout = config.timeout_s.map(Duration::from_secs);
+        // Set even when already energized, an emergency stop may have
+        // disabled them behind our back.
+        drivers.en_pin.set_low()?;
+        drivers.busy = true;
+        let was_off = !drivers.energized;
+        drivers.energized = true;
+        was_off
+    };
+    let busy = DriversBusy;
+    if was_off {
+        sleep(Duration::from_millis(config.settle_ms)).await;
+    }
+    Ok((busy, was_off))
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::tool::ToolOffset;
+
+/// Rectangle of the bed the carriage must never enter or cross, such as a
+/// reservoir or the frame around a pump.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KeepOutZone {
+    pub name: String,
+    pub x_min: u32,
+    pub x_max: u32,
+    pub y_min: u32,
+    pub y_max: u32,
+}
+
+impl KeepOutZone {
+    fn contains(&self, (x, y): (f64, f64)) -> bool {
+        (self.x_min as f64..=self.x_max as f64).contains(&x)
+            && (self.y_min as f64..=self.y_max as f64).contains(&y)
+    }
+
+    /// Liang-Barsky clipping of the straight segment against the rectangle.
+    fn crossed_by(&self, from: (f64, f64), to: (f64, f64)) -> bool {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let (mut t0, mut t1) = (0.0f64, 1.0f64);
+        for (p, q) in [
+            (-dx, from.0 - self.x_min as f64),
+            (dx, self.x_max as f64 - from.0),
+            (-dy, from.1 - self.y_min as f64),
+            (dy, self.y_max as f64 - from.1),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return false;
+                }
+            } else {
+                let r = q / p;
+                if p < 0.0 {
+                    t0 = t0.max(r);
+                } else {
+                    t1 = t1.min(r);
+                }
+                if t0 > t1 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveError {
+    OutOfTravel {
+        axis: char,
+        value: u32,
+        limit: u32,
+    },
+    KeepOut {
+        zone: String,
+        x: u32,
+        y: u32,
+    },
+    NoAxis {
+        axis: char,
+    },
+    ToolOffset {
+        tool: String,
+        axis: char,
+        value: u32,
+    },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::OutOfTravel { axis, value, limit } => {
+                write!(
+                    f,
+                    "{axis} = {value} mm is outside the 0..={limit} mm travel"
+                )
+            }
+            MoveError::KeepOut { zone, x, y } => {
+                write!(f, "move to ({x}, {y}) enters keep-out zone `{zone}`")
+            }
+            MoveError::NoAxis { axis } => write!(f, "no {axis} axis configured"),
+            MoveError::ToolOffset { tool, axis, value } => {
+                write!(
+                    f,
+                    "the {tool} offset puts the carriage below 0 to reach {axis} = {value} mm"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// Reachable part of the bed: the soft limits of each axis minus the
+/// keep-out zones.
+#[derive(Debug, Clone, Default)]
+pub struct TravelLimits {
+    pub x_limit: u32,
+    pub y_limit: u32,
+    pub z_limit: Option<u32>,
+    pub keep_out: Vec<KeepOutZone>,
+    pub tools: BTreeMap<String, ToolOffset>,
+}
+
+impl TravelLimits {
+    /// Carriage coordinate on `axis` that puts `tool` at `target`. Tools
+    /// without a configured offset sit on the carriage reference point.
+    fn carriage(&self, tool: &str, axis: char, target: u32) -> Result<u32, MoveError> {
+        let offset = self.tools.get(tool).copied().unwrap_or_default();
+        let offset = match axis {
+            'x' => offset.x,
+            'y' => offset.y,
+            _ => offset.z,
+        };
+        u32::try_from(target as i64 - offset as i64).map_err(|_| MoveError::ToolOffset {
+            tool: tool.to_owned(),
+            axis,
+            value: target,
+        })
+    }
+
+    /// Carriage position putting `tool` over `(x, y)`.
+    pub fn carriage_xy(&self, tool: &str, x: u32, y: u32) -> Result<(u32, u32), MoveError> {
+        Ok((self.carriage(tool, 'x', x)?, self.carriage(tool, 'y', y)?))
+    }
+
+    /// Carriage height lowering `tool` to `z`.
+    pub fn carriage_z(&self, tool: &str, z: u32) -> Result<u32, MoveError> {
+        self.carriage(tool, 'z', z)
+    }
+
+    /// Checks that `tool` can be brought over `(x, y)`, and down to `z`.
+    pub fn validate_tool(
+        &self,
+        tool: &str,
+        x: u32,
+        y: u32,
+        z: Option<u32>,
+    ) -> Result<(), MoveError> {
+        let (x, y) = self.carriage_xy(tool, x, y)?;
+        self.validate(None, x, y)?;
+        if let Some(z) = z {
+            self.validate_z(self.carriage_z(tool, z)?)?;
+        }
+        Ok(())
+    }
+
+    /// Checks a height, 0 being the raised position the Z axis travels at.
+    pub fn validate_z(&self, z: u32) -> Result<(), MoveError> {
+        match self.z_limit {
+            None => Err(MoveError::NoAxis { axis: 'z' }),
+            Some(limit) if z > limit => Err(MoveError::OutOfTravel {
+                axis: 'z',
+                value: z,
+                limit,
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Checks the target and, when the start is known, the straight path to
+    /// it. Zones the carriage already sits in are ignored so it can leave.
+    pub fn validate(&self, from: Option<(u32, u32)>, x: u32, y: u32) -> Result<(), MoveError> {
+        if x > self.x_limit {
+            return Err(MoveError::OutOfTravel {
+                axis: 'x',
+                value: x,
+                limit: self.x_limit,
+            });
+        }
+        if y > self.y_limit {
+            return Err(MoveError::OutOfTravel {
+                axis: 'y',
+                value: y,
+                limit: self.y_limit,
+            });
+        }
+        let to = (x as f64, y as f64);
+        let from = from.map(|(x, y)| (x as f64, y as f64));
+        for zone in &self.keep_out {
+            let hit = match from {
+                Some(from) if zone.contains(from) => false,
+                Some(from) => zone.crossed_by(from, to),
+                None => zone.contains(to),
+            };
+            if hit {
+                return Err(MoveError::KeepOut {
+                    zone: zone.name.clone(),
+                    x,
+                    y,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100 by 100 bed with a reservoir in the middle.
+    fn limits() -> TravelLimits {
+        TravelLimits {
+            x_limit: 100,
+            y_limit: 100,
+            keep_out: vec![KeepOutZone {
+                name: "reservoir".to_owned(),
+                x_min: 40,
+                x_max: 60,
+                y_min: 40,
+                y_max: 60,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn zone() -> KeepOutZone {
+        limits().keep_out.remove(0)
+    }
+
+    #[test]
+    fn segments_crossing_or_touching_the_zone() {
+        let zone = zone();
+        assert!(zone.crossed_by((0.0, 50.0), (100.0, 50.0)));
+        assert!(zone.crossed_by((0.0, 0.0), (100.0, 100.0)));
+        assert!(zone.crossed_by((30.0, 50.0), (50.0, 50.0)));
+        // Grazing an edge or a corner counts.
+        assert!(zone.crossed_by((0.0, 40.0), (100.0, 40.0)));
+        assert!(zone.crossed_by((0.0, 80.0), (80.0, 0.0)));
+    }
+
+    #[test]
+    fn segments_missing_the_zone() {
+        let zone = zone();
+        assert!(!zone.crossed_by((0.0, 30.0), (100.0, 30.0)));
+        assert!(!zone.crossed_by((0.0, 0.0), (30.0, 30.0)));
+        assert!(!zone.crossed_by((0.0, 70.0), (70.0, 0.0)));
+        // Parallel to an edge, just outside.
+        assert!(!zone.crossed_by((61.0, 0.0), (61.0, 100.0)));
+        // Stops short of the zone.
+        assert!(!zone.crossed_by((0.0, 50.0), (39.0, 50.0)));
+        // A single point.
+        assert!(!zone.crossed_by((10.0, 10.0), (10.0, 10.0)));
+        assert!(zone.crossed_by((50.0, 50.0), (50.0, 50.0)));
+    }
+
+    #[test]
+    fn validate_checks_limits_target_and_path() {
+        let limits = limits();
+        assert!(matches!(
+            limits.validate(None, 101, 0),
+            Err(MoveError::OutOfTravel { axis: 'x', .. })
+        ));
+        assert!(matches!(
+            limits.validate(None, 0, 101),
+            Err(MoveError::OutOfTravel { axis: 'y', .. })
+        ));
+        assert!(limits.validate(None, 50, 50).is_err());
+        assert!(limits.validate(None, 100, 50).is_ok());
+        assert!(limits.validate(Some((0, 50)), 100, 50).is_err());
+        assert!(limits.validate(Some((0, 20)), 100, 20).is_ok());
+        // Leaving a zone the carriage is in is allowed.
+        assert!(limits.validate(Some((50, 50)), 100, 50).is_ok());
+    }
+}
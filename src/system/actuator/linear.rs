@@ -1,78 +1,282 @@
 use std::time::Duration;
 
+use anyhow::bail;
 use async_std::task::sleep;
 use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
 
-use super::gpio_pin::LocalGpioConfig;
+use super::{estop, gpio_pin::LocalGpioConfig};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HomingDirection {
+    #[default]
+    Min,
+    Max,
+}
 
 #[derive(Getters, Serialize, Deserialize, Debug, Clone)]
-pub struct LocalLinearConfig {
-    pub reverse: bool,
-    pub step_pin: LocalGpioConfig,
-    pub dir_pin: LocalGpioConfig,
+pub struct HomingConfig {
+    pub limit_pin: LocalGpioConfig,
+    pub active_low: bool,
+    pub direction: HomingDirection,
+    pub mm_per_s: u32,
+    pub back_off: u32,
+}
+
+impl Default for HomingConfig {
+    fn default() -> Self {
+        Self {
+            limit_pin: Default::default(),
+            active_low: false,
+            direction: HomingDirection::Min,
+            mm_per_s: 10,
+            back_off: 5,
+        }
+    }
+}
+
+impl HomingConfig {
+    pub fn triggered(&mut self) -> anyhow::Result<bool> {
+        Ok(self.limit_pin.is_high()? ^ self.active_low)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionProfile {
+    #[default]
+    Sine,
+    Trapezoidal,
+    SCurve,
+}
+
+fn default_acceleration() -> f32 {
+    200.0
+}
+
+fn default_jerk() -> f32 {
+    2000.0
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct AxisConfig {
     pub limit: u32,
     pub position: u32,
     pub min_mm_per_s: u32,
     pub max_mm_per_s: u32,
-    pub step_per_mm: u32,
+    /// May be fractional, e.g. 26.67 for a 60-tooth GT2 pulley at 1/16
+    /// microstepping.
+    pub step_per_mm: f64,
+    #[serde(default)]
+    pub profile: MotionProfile,
+    /// mm/s², used by the trapezoidal and S-curve profiles.
+    #[serde(default = "default_acceleration")]
+    pub acceleration: f32,
+    /// mm/s³, used by the S-curve profile.
+    #[serde(default = "default_jerk")]
+    pub jerk: f32,
 }
 
-impl Default for LocalLinearConfig {
+impl Default for AxisConfig {
     fn default() -> Self {
         Self {
-            reverse: false,
-            step_pin: Default::default(),
-            dir_pin: Default::default(),
             limit: 200,
             position: 0,
             min_mm_per_s: 10,
             max_mm_per_s: 50,
-            step_per_mm: 40,
+            step_per_mm: 40.0,
+            profile: MotionProfile::Sine,
+            acceleration: default_acceleration(),
+            jerk: default_jerk(),
         }
     }
 }
 
+impl AxisConfig {
+    /// Absolute step count of `mm` from zero. Rounding the absolute count
+    /// rather than each move's distance keeps fractional steps per mm from
+    /// accumulating error.
+    pub fn steps_at(&self, mm: u32) -> u32 {
+        (mm as f64 * self.step_per_mm).round() as u32
+    }
+
+    /// Moves `position` to where the carriage stopped after `pulses` steps of
+    /// an interrupted move, rounded to the nearest mm.
+    pub fn stopped_after(&mut self, pulses: u32, decreasing: bool) {
+        let from = self.steps_at(self.position);
+        let at = if decreasing {
+            from.saturating_sub(pulses)
+        } else {
+            from + pulses
+        };
+        self.position = (at as f64 / self.step_per_mm).round() as u32;
+    }
+}
+
+/// Side from which the carriage must arrive at a target, so that the belt is
+/// always tensioned the same way at rest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Approach {
+    FromMin,
+    FromMax,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LocalLinearConfig {
+    pub reverse: bool,
+    pub step_pin: LocalGpioConfig,
+    pub dir_pin: LocalGpioConfig,
+    #[serde(flatten)]
+    pub axis: AxisConfig,
+    #[serde(default)]
+    pub homing: Option<HomingConfig>,
+    /// Slack in mm taken up with extra steps whenever the axis reverses.
+    #[serde(default)]
+    pub backlash: f64,
+    #[serde(default)]
+    pub approach: Option<Approach>,
+    #[serde(skip)]
+    homed: bool,
+    /// Direction of the last move, `true` when decreasing. The side the slack
+    /// sits on is unknown until the axis has moved.
+    #[serde(skip)]
+    last_decreasing: Option<bool>,
+}
+
 impl LocalLinearConfig {
-    pub async fn goto(&mut self, position: u32) -> anyhow::Result<u32> {
-        let step_pos = position * self.step_per_mm();
-        let mut this_step_pos = self.position * self.step_per_mm();
+    /// Lines used by the axis, named after their role.
+    pub fn gpio_lines(&self, axis: &str) -> Vec<(String, LocalGpioConfig)> {
+        let mut lines = vec![
+            (format!("{axis}:step"), self.step_pin.clone()),
+            (format!("{axis}:dir"), self.dir_pin.clone()),
+        ];
+        if let Some(homing) = &self.homing {
+            lines.push((format!("{axis}:limit"), homing.limit_pin.clone()));
+        }
+        lines
+    }
+
+    /// Axes without a limit switch trust the `position` stored in the config,
+    /// the others only once [`Self::home`] has succeeded.
+    pub fn position_known(&self) -> bool {
+        self.homing.is_none() || self.homed
+    }
+
+    /// The carriage may coast after an abrupt stop, so a homed axis needs
+    /// homing again.
+    pub fn stopped_after(&mut self, pulses: u32, decreasing: bool) {
+        self.axis.stopped_after(pulses, decreasing);
+        self.homed = false;
+    }
+
+    /// Steps needed to take up the slack before moving in the given
+    /// direction.
+    pub fn backlash_steps(&self, decreasing: bool) -> u32 {
+        match self.last_decreasing {
+            Some(last) if last != decreasing => {
+                (self.backlash.max(0.0) * self.axis.step_per_mm).round() as u32
+            }
+            _ => 0,
+        }
+    }
+
+    /// Distance past the target the approach detour goes, clearing the
+    /// backlash by at least a millimetre.
+    pub fn overshoot(&self) -> u32 {
+        self.backlash.max(0.0).ceil() as u32 + 1
+    }
+
+    /// Intermediate target from which `target` is reached in the configured
+    /// approach direction, if the direct move would arrive from the other
+    /// side. The detour stops short at either end of the travel.
+    pub fn approach_via(&self, target: u32) -> Option<u32> {
+        let overshoot = self.overshoot();
+        match self.approach? {
+            Approach::FromMin if target < self.axis.position => {
+                Some(target.saturating_sub(overshoot))
+            }
+            Approach::FromMax if target > self.axis.position => {
+                Some((target + overshoot).min(self.axis.limit))
+            }
+            _ => None,
+        }
+    }
+
+    /// Called when the carriage may have been moved by hand. Returns whether
+    /// the axis can be homed to recover its position.
+    pub fn forget_position(&mut self) -> bool {
+        self.homed = false;
+        self.last_decreasing = None;
+        self.homing.is_some()
+    }
 
-        let max_step_ps = self.max_mm_per_s * self.step_per_mm();
-        let min_step_ps = self.min_mm_per_s * self.step_per_mm();
+    pub fn moved(&mut self, decreasing: bool) {
+        self.last_decreasing = Some(decreasing);
+    }
 
-        if (step_pos < this_step_pos) ^ self.reverse() {
+    pub fn set_direction(&mut self, decreasing: bool) -> anyhow::Result<()> {
+        if decreasing ^ self.reverse() {
             self.dir_pin.set_low()?;
         } else {
             self.dir_pin.set_high()?;
         }
+        Ok(())
+    }
 
-        sleep(Duration::from_millis(1)).await;
+    async fn step(&mut self, interval: Duration) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        self.step_pin.set_high()?;
+        sleep(Duration::from_micros(2)).await;
+        self.step_pin.set_low()?;
+        sleep(interval).await;
+        Ok(())
+    }
 
-        let step = this_step_pos.abs_diff(step_pos);
+    pub async fn home(&mut self) -> anyhow::Result<u32> {
+        let Some(mut homing) = self.homing.clone() else {
+            return Ok(self.axis.position);
+        };
+        self.homed = false;
 
-        while this_step_pos != step_pos {
-            let idx = (this_step_pos.abs_diff(step_pos) * 999) / step;
+        let interval = Duration::from_secs_f64(
+            1.0 / (homing.mm_per_s as f64 * self.axis.step_per_mm).max(1.0),
+        );
+        let toward_min = homing.direction == HomingDirection::Min;
 
-            let v = SINE_TO_PI[idx as usize] * (max_step_ps - min_step_ps) + min_step_ps * 1000;
+        // The carriage may start anywhere, so allow a full stroke plus the
+        // back-off before giving up on the switch.
+        let max_steps = self.axis.steps_at(self.axis.limit + homing.back_off);
 
-            self.step_pin.set_high()?;
-            sleep(Duration::from_micros(2)).await;
-            self.step_pin.set_low()?;
-            sleep(Duration::from_micros(1_000_000_000 / v as u64)).await;
-            if this_step_pos < step_pos {
-                this_step_pos += 1;
-            } else {
-                this_step_pos -= 1;
+        self.set_direction(toward_min)?;
+        sleep(Duration::from_millis(1)).await;
+        let mut steps = 0;
+        while !homing.triggered()? {
+            if steps >= max_steps {
+                bail!("limit switch not reached after {} steps", steps);
             }
+            self.step(interval).await?;
+            steps += 1;
+        }
+
+        self.set_direction(!toward_min)?;
+        sleep(Duration::from_millis(1)).await;
+        for _ in 0..self.axis.steps_at(homing.back_off) {
+            self.step(interval).await?;
         }
+        self.last_decreasing = Some(!toward_min);
 
-        self.position = position;
-        Ok(position)
+        self.axis.position = match homing.direction {
+            HomingDirection::Min => homing.back_off,
+            HomingDirection::Max => self.axis.limit.saturating_sub(homing.back_off),
+        };
+        self.homed = true;
+        Ok(self.axis.position)
     }
 }
 
-const SINE_TO_PI: [u32; 1000] = [
+pub const SINE_TO_PI: [u32; 1000] = [
     0, 3, 6, 9, 12, 15, 18, 21, 25, 28, 31, 34, 37, 40, 43, 47, 50, 53, 56, 59, 62, 65, 69, 72, 75,
     78, 81, 84, 87, 90, 94, 97, 100, 103, 106, 109, 112, 115, 119, 122, 125, 128, 131, 134, 137,
     140, 144, 147, 150, 153, 156, 159, 162, 165, 168, 171, 175, 178, 181, 184, 187, 190, 193, 196,
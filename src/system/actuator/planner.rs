@@ -0,0 +1,427 @@
+use std::time::Duration;
+
+use super::linear::{AxisConfig, MotionProfile, SINE_TO_PI};
+
+/// One tick of a coordinated move: which axes pulse, then how long to wait
+/// before the next tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlannedStep {
+    pub x: bool,
+    pub y: bool,
+    pub interval: Duration,
+}
+
+/// Straight-line XY move. Both axes share one velocity profile along the path
+/// and finish on the same tick.
+#[derive(Debug, Clone, Default)]
+pub struct CoordinatedPlan {
+    pub x_decreasing: bool,
+    pub y_decreasing: bool,
+    pub steps: Vec<PlannedStep>,
+}
+
+impl CoordinatedPlan {
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.interval).sum()
+    }
+
+    /// Number of ticks already pulsed `elapsed` after the move started.
+    pub fn ticks_within(&self, elapsed: Duration) -> usize {
+        ticks_within(self.steps.iter().map(|step| step.interval), elapsed)
+    }
+
+    /// X and Y pulses emitted by the first `ticks` ticks.
+    pub fn pulses(&self, ticks: usize) -> (u32, u32) {
+        self.steps[..ticks.min(self.steps.len())]
+            .iter()
+            .fold((0, 0), |(x, y), step| {
+                (x + step.x as u32, y + step.y as u32)
+            })
+    }
+}
+
+/// Move of a single axis, pulsing on every tick.
+#[derive(Debug, Clone, Default)]
+pub struct AxisPlan {
+    pub decreasing: bool,
+    pub intervals: Vec<Duration>,
+}
+
+impl AxisPlan {
+    pub fn duration(&self) -> Duration {
+        self.intervals.iter().sum()
+    }
+
+    /// Number of pulses already emitted `elapsed` after the move started.
+    pub fn ticks_within(&self, elapsed: Duration) -> usize {
+        ticks_within(self.intervals.iter().copied(), elapsed)
+    }
+}
+
+fn ticks_within(intervals: impl Iterator<Item = Duration>, elapsed: Duration) -> usize {
+    let mut at = Duration::ZERO;
+    intervals
+        .take_while(|interval| {
+            let started = at <= elapsed;
+            at += *interval;
+            started
+        })
+        .count()
+}
+
+/// Path speed in mm/s as a function of the distance travelled.
+enum VelocityProfile {
+    Sine {
+        length: f64,
+        min: f64,
+        max: f64,
+    },
+    /// Symmetric acceleration and deceleration around a cruise at `peak`.
+    /// `ramp` holds `(distance, speed)` samples of the acceleration phase.
+    Ramp {
+        length: f64,
+        peak: f64,
+        ramp: Vec<(f64, f64)>,
+    },
+}
+
+/// Jerk time, constant acceleration time and peak acceleration of a ramp
+/// from `v0` to `vp`. Without a jerk limit the ramp is a straight line.
+fn ramp_times(v0: f64, vp: f64, accel: f64, jerk: Option<f64>) -> (f64, f64, f64) {
+    let dv = vp - v0;
+    match jerk {
+        None => (0.0, dv / accel, accel),
+        Some(jerk) if dv >= accel * accel / jerk => {
+            (accel / jerk, dv / accel - accel / jerk, accel)
+        }
+        Some(jerk) => {
+            let tj = (dv / jerk).sqrt();
+            (tj, 0.0, jerk * tj)
+        }
+    }
+}
+
+fn ramp_distance(v0: f64, vp: f64, accel: f64, jerk: Option<f64>) -> f64 {
+    let (tj, ta, _) = ramp_times(v0, vp, accel, jerk);
+    (v0 + vp) / 2.0 * (2.0 * tj + ta)
+}
+
+fn ramp_speed(t: f64, v0: f64, tj: f64, ta: f64, ap: f64) -> f64 {
+    let jerk = if tj > 0.0 { ap / tj } else { 0.0 };
+    if t < tj {
+        v0 + jerk * t * t / 2.0
+    } else if t < tj + ta {
+        v0 + jerk * tj * tj / 2.0 + ap * (t - tj)
+    } else {
+        let u = (t - tj - ta).min(tj);
+        v0 + jerk * tj * tj / 2.0 + ap * ta + ap * u - jerk * u * u / 2.0
+    }
+}
+
+impl VelocityProfile {
+    fn new(
+        kind: MotionProfile,
+        length: f64,
+        min: f64,
+        max: f64,
+        accel: f64,
+        jerk: f64,
+    ) -> VelocityProfile {
+        let jerk = match kind {
+            MotionProfile::Sine => return VelocityProfile::Sine { length, min, max },
+            MotionProfile::Trapezoidal => None,
+            MotionProfile::SCurve => Some(jerk.max(1.0)),
+        };
+        let accel = accel.max(1.0);
+
+        // Short moves never reach `max`; find the highest peak whose
+        // acceleration and deceleration still fit in the path.
+        let mut peak = max;
+        if 2.0 * ramp_distance(min, max, accel, jerk) > length {
+            let (mut lo, mut hi) = (min, max);
+            for _ in 0..40 {
+                let mid = (lo + hi) / 2.0;
+                if 2.0 * ramp_distance(min, mid, accel, jerk) > length {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            peak = lo;
+        }
+
+        const SAMPLES: usize = 256;
+        let (tj, ta, ap) = ramp_times(min, peak, accel, jerk);
+        let dt = (2.0 * tj + ta) / SAMPLES as f64;
+        let mut ramp = Vec::with_capacity(SAMPLES + 1);
+        let (mut distance, mut speed) = (0.0, min);
+        ramp.push((distance, speed));
+        for i in 1..=SAMPLES {
+            let next = ramp_speed(i as f64 * dt, min, tj, ta, ap);
+            distance += (speed + next) / 2.0 * dt;
+            speed = next;
+            ramp.push((distance, speed));
+        }
+
+        VelocityProfile::Ramp { length, peak, ramp }
+    }
+
+    fn speed(&self, distance: f64) -> f64 {
+        match self {
+            VelocityProfile::Sine { length, min, max } => {
+                let idx = (((length - distance) / length) * 999.0) as usize;
+                min + (max - min) * SINE_TO_PI[idx.min(999)] as f64 / 1000.0
+            }
+            VelocityProfile::Ramp { length, peak, ramp } => {
+                let from_end = distance.min(length - distance).max(0.0);
+                let idx = ramp.partition_point(|(d, _)| *d < from_end);
+                match (ramp.get(idx.wrapping_sub(1)), ramp.get(idx)) {
+                    (Some((d0, v0)), Some((d1, v1))) if d1 > d0 => {
+                        v0 + (v1 - v0) * (from_end - d0) / (d1 - d0)
+                    }
+                    (_, Some((_, v))) => *v,
+                    _ => *peak,
+                }
+            }
+        }
+    }
+}
+
+pub fn plan_xy(x_axis: &AxisConfig, x: u32, y_axis: &AxisConfig, y: u32) -> CoordinatedPlan {
+    let x_steps = x_axis.steps_at(x) as i64 - x_axis.steps_at(x_axis.position) as i64;
+    let y_steps = y_axis.steps_at(y) as i64 - y_axis.steps_at(y_axis.position) as i64;
+    plan_steps(x_axis, x_steps, y_axis, y_steps)
+}
+
+pub fn plan_axis(axis: &AxisConfig, target: u32) -> AxisPlan {
+    plan_axis_steps(
+        axis,
+        axis.steps_at(target) as i64 - axis.steps_at(axis.position) as i64,
+    )
+}
+
+/// Single-axis move by a signed step count, negative being decreasing.
+pub fn plan_axis_steps(axis: &AxisConfig, steps: i64) -> AxisPlan {
+    let mut plan = AxisPlan {
+        decreasing: steps < 0,
+        intervals: Vec::with_capacity(steps.unsigned_abs() as usize),
+    };
+    let steps = steps.unsigned_abs() as u32;
+    if steps == 0 {
+        return plan;
+    }
+
+    let length = steps as f64 / axis.step_per_mm;
+    let max_speed = axis.max_mm_per_s as f64;
+    let profile = VelocityProfile::new(
+        axis.profile,
+        length,
+        (axis.min_mm_per_s as f64).min(max_speed),
+        max_speed,
+        axis.acceleration as f64,
+        axis.jerk as f64,
+    );
+    for done in 0..steps {
+        let speed = profile.speed(length * done as f64 / steps as f64);
+        let rate = speed * axis.step_per_mm;
+        plan.intervals
+            .push(Duration::from_secs_f64(1.0 / rate.max(1.0)));
+    }
+    plan
+}
+
+/// Straight-line move by signed step counts, negative being decreasing.
+pub fn plan_steps(
+    x_axis: &AxisConfig,
+    x_steps: i64,
+    y_axis: &AxisConfig,
+    y_steps: i64,
+) -> CoordinatedPlan {
+    let mut plan = CoordinatedPlan {
+        x_decreasing: x_steps < 0,
+        y_decreasing: y_steps < 0,
+        steps: Vec::with_capacity(x_steps.unsigned_abs().max(y_steps.unsigned_abs()) as usize),
+    };
+    let x_steps = x_steps.unsigned_abs() as u32;
+    let y_steps = y_steps.unsigned_abs() as u32;
+    if x_steps == 0 && y_steps == 0 {
+        return plan;
+    }
+
+    let dx = x_steps as f64 / x_axis.step_per_mm;
+    let dy = y_steps as f64 / y_axis.step_per_mm;
+    let length = dx.hypot(dy);
+
+    // Path limits such that neither axis leaves its own speed, acceleration
+    // and jerk range. The most constrained profile of the moving axes wins.
+    let mut min_speed = f64::MAX;
+    let mut max_speed = f64::MAX;
+    let mut accel = f64::MAX;
+    let mut jerk = f64::MAX;
+    let mut kind = MotionProfile::Sine;
+    for (distance, axis) in [(dx, x_axis), (dy, y_axis)] {
+        if distance > 0.0 {
+            let scale = length / distance;
+            min_speed = min_speed.min(axis.min_mm_per_s as f64 * scale);
+            max_speed = max_speed.min(axis.max_mm_per_s as f64 * scale);
+            accel = accel.min(axis.acceleration as f64 * scale);
+            jerk = jerk.min(axis.jerk as f64 * scale);
+            kind = kind.max(axis.profile);
+        }
+    }
+    let min_speed = min_speed.min(max_speed);
+    let profile = VelocityProfile::new(kind, length, min_speed, max_speed, accel, jerk);
+
+    // Bresenham over the axis with more steps; the other axis pulses on the
+    // same ticks whenever its error term overflows.
+    let x_major = x_steps >= y_steps;
+    let (major_steps, minor_steps) = if x_major {
+        (x_steps, y_steps)
+    } else {
+        (y_steps, x_steps)
+    };
+    let (major_mm, major_step_per_mm) = if x_major {
+        (dx, x_axis.step_per_mm)
+    } else {
+        (dy, y_axis.step_per_mm)
+    };
+
+    let mut error = major_steps / 2;
+    for done in 0..major_steps {
+        let speed = profile.speed(length * done as f64 / major_steps as f64);
+        let major_rate = speed * major_mm / length * major_step_per_mm;
+
+        let mut minor = false;
+        if error < minor_steps {
+            error += major_steps;
+            minor = true;
+        }
+        error -= minor_steps;
+
+        plan.steps.push(PlannedStep {
+            x: x_major || minor,
+            y: !x_major || minor,
+            interval: Duration::from_secs_f64(1.0 / major_rate.max(1.0)),
+        });
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Axis with the default settings, resting at `position` mm.
+    fn at(position: u32) -> AxisConfig {
+        AxisConfig {
+            position,
+            ..Default::default()
+        }
+    }
+
+    fn pulses(plan: &CoordinatedPlan) -> (usize, usize) {
+        let x = plan.steps.iter().filter(|step| step.x).count();
+        let y = plan.steps.iter().filter(|step| step.y).count();
+        (x, y)
+    }
+
+    #[test]
+    fn plan_reaches_both_endpoints() {
+        let x_axis = at(10);
+        let y_axis = at(0);
+        let plan = plan_xy(&x_axis, 60, &y_axis, 35);
+        assert_eq!(pulses(&plan), (2000, 1400));
+        assert_eq!(plan.steps.len(), 2000);
+        assert!(!plan.x_decreasing && !plan.y_decreasing);
+    }
+
+    #[test]
+    fn minor_axis_pulses_are_spread_evenly() {
+        let x_axis = at(30);
+        let plan = plan_xy(&x_axis, 0, &at(0), 10);
+        assert!(plan.x_decreasing && !plan.y_decreasing);
+        assert!(plan.steps.iter().all(|step| step.x));
+        let ticks: Vec<_> = (0..plan.steps.len()).filter(|&i| plan.steps[i].y).collect();
+        assert_eq!(ticks.len(), 400);
+        assert!(ticks.windows(2).all(|pair| pair[1] - pair[0] == 3));
+    }
+
+    #[test]
+    fn diagonal_pulses_both_axes_every_tick() {
+        let y_axis = at(20);
+        let plan = plan_xy(&at(0), 20, &y_axis, 0);
+        assert_eq!(plan.steps.len(), 800);
+        assert!(plan.steps.iter().all(|step| step.x && step.y));
+        assert!(plan.y_decreasing);
+    }
+
+    #[test]
+    fn degenerate_moves() {
+        let x_axis = at(5);
+        let y_axis = at(0);
+        assert!(plan_xy(&x_axis, 5, &y_axis, 0).steps.is_empty());
+
+        let plan = plan_xy(&x_axis, 5, &y_axis, 5);
+        assert_eq!(pulses(&plan), (0, 200));
+        assert!(plan.steps.iter().all(|step| step.interval > Duration::ZERO));
+    }
+
+    fn axis(profile: MotionProfile) -> AxisConfig {
+        AxisConfig {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    /// Intervals shrink while accelerating and grow back while decelerating.
+    fn assert_monotonic(plan: &CoordinatedPlan) {
+        let intervals: Vec<_> = plan.steps.iter().map(|step| step.interval).collect();
+        let half = intervals.len() / 2;
+        assert!(intervals[..half].windows(2).all(|w| w[1] <= w[0]));
+        assert!(intervals[half..].windows(2).all(|w| w[1] >= w[0]));
+    }
+
+    #[test]
+    fn ramps_are_monotonic_and_cruise_at_full_speed() {
+        for profile in [MotionProfile::Trapezoidal, MotionProfile::SCurve] {
+            let plan = plan_xy(&axis(profile), 150, &axis(profile), 0);
+            assert_monotonic(&plan);
+            // From 10 mm/s up to 50 mm/s, at 40 steps per mm.
+            assert_eq!(plan.steps[0].interval, Duration::from_secs_f64(1.0 / 400.0));
+            let cruise = plan.steps[plan.steps.len() / 2].interval;
+            assert_eq!(cruise, Duration::from_secs_f64(1.0 / 2000.0), "{profile:?}");
+        }
+    }
+
+    #[test]
+    fn short_moves_peak_below_full_speed() {
+        for profile in [MotionProfile::Trapezoidal, MotionProfile::SCurve] {
+            let plan = plan_xy(&axis(profile), 2, &axis(profile), 0);
+            assert_monotonic(&plan);
+            let fastest = plan.steps.iter().map(|step| step.interval).min().unwrap();
+            assert!(
+                fastest > Duration::from_secs_f64(1.0 / 2000.0),
+                "{profile:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn single_axis_plan_matches_a_move_along_that_axis() {
+        for profile in [MotionProfile::Sine, MotionProfile::SCurve] {
+            let z_axis = AxisConfig {
+                position: 30,
+                ..axis(profile)
+            };
+            let plan = plan_axis(&z_axis, 5);
+            assert!(plan.decreasing);
+            assert_eq!(plan.intervals.len(), 1000);
+            let xy = plan_xy(&z_axis, 5, &at(0), 0);
+            let intervals: Vec<_> = xy.steps.iter().map(|step| step.interval).collect();
+            assert_eq!(plan.intervals, intervals, "{profile:?}");
+            assert_eq!(plan.duration(), xy.duration());
+        }
+        assert!(plan_axis(&at(7), 7).intervals.is_empty());
+        assert_eq!(plan_axis_steps(&at(7), 3).intervals.len(), 3);
+    }
+}
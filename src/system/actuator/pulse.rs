@@ -0,0 +1,184 @@
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use futures::channel::oneshot;
+use once_cell::sync::Lazy;
+
+use super::{
+    estop,
+    gpio_pin::LocalGpioConfig,
+    planner::{AxisPlan, CoordinatedPlan, PlannedStep},
+};
+
+/// How late pulses were emitted compared to the planned timeline.
+#[derive(Debug, Clone, Default)]
+pub struct JitterStats {
+    pub pulses: u64,
+    pub mean: Duration,
+    pub max: Duration,
+    pub over_100us: u64,
+    total: Duration,
+}
+
+impl JitterStats {
+    fn record(&mut self, late: Duration) {
+        self.pulses += 1;
+        self.total += late;
+        self.max = self.max.max(late);
+        if late > Duration::from_micros(100) {
+            self.over_100us += 1;
+        }
+        self.mean = self.total / self.pulses as u32;
+    }
+}
+
+/// Pulses emitted by a move. A single-axis move counts them in `x_pulses`.
+#[derive(Debug, Clone, Default)]
+pub struct PulseReport {
+    pub jitter: JitterStats,
+    pub x_pulses: u32,
+    pub y_pulses: u32,
+    pub aborted: bool,
+}
+
+struct PulseJob {
+    steps: Vec<PlannedStep>,
+    x_pin: LocalGpioConfig,
+    /// `None` for a single-axis move.
+    y_pin: Option<LocalGpioConfig>,
+    done: oneshot::Sender<anyhow::Result<PulseReport>>,
+}
+
+static PULSE_THREAD: Lazy<Mutex<Sender<PulseJob>>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<PulseJob>();
+    thread::Builder::new()
+        .name("step-pulse".to_owned())
+        .spawn(move || {
+            set_realtime_priority();
+            for job in receiver {
+                let res = run(&job.steps, &job.x_pin, job.y_pin.as_ref());
+                job.done.send(res).ok();
+            }
+        })
+        .expect("unable to spawn step pulse thread");
+    Mutex::new(sender)
+});
+
+fn set_realtime_priority() {
+    let param = libc::sched_param { sched_priority: 50 };
+    // SAFETY: plain syscalls on the calling thread with valid parameters.
+    let res = unsafe {
+        libc::prctl(libc::PR_SET_TIMERSLACK, 1 as libc::c_ulong);
+        libc::sched_setscheduler(0, libc::SCHED_FIFO, &param)
+    };
+    if res != 0 {
+        log::warn!(
+            "Step pulse thread runs without real-time priority: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Sleeps for most of the wait and spins for the rest, the kernel timer
+/// slack being larger than a step interval at full speed.
+fn wait_until(due: Instant) {
+    const SPIN: Duration = Duration::from_micros(200);
+    let now = Instant::now();
+    if due > now + SPIN {
+        thread::sleep(due - now - SPIN);
+    }
+    while Instant::now() < due {
+        std::hint::spin_loop();
+    }
+}
+
+fn run(
+    steps: &[PlannedStep],
+    x_pin: &LocalGpioConfig,
+    y_pin: Option<&LocalGpioConfig>,
+) -> anyhow::Result<PulseReport> {
+    // Looked up before the first pulse, so that the loop shares no lock with
+    // the other threads driving lines.
+    let x_pin = x_pin.output()?;
+    let y_pin = y_pin.map(LocalGpioConfig::output).transpose()?;
+    let mut report = PulseReport::default();
+    // Deadlines are absolute so that a late pulse does not delay the rest of
+    // the move.
+    let mut due = Instant::now();
+    for step in steps {
+        wait_until(due);
+        if estop::is_halted() {
+            report.aborted = true;
+            return Ok(report);
+        }
+        report
+            .jitter
+            .record(Instant::now().saturating_duration_since(due));
+        if step.x {
+            x_pin.set_high()?;
+            report.x_pulses += 1;
+        }
+        if let (true, Some(y_pin)) = (step.y, &y_pin) {
+            y_pin.set_high()?;
+            report.y_pulses += 1;
+        }
+        wait_until(Instant::now() + Duration::from_micros(2));
+        if step.x {
+            x_pin.set_low()?;
+        }
+        if let (true, Some(y_pin)) = (step.y, &y_pin) {
+            y_pin.set_low()?;
+        }
+        due += step.interval;
+    }
+    wait_until(due);
+    Ok(report)
+}
+
+/// Hands the plan to the step pulse thread and waits for the move to finish
+/// or to stop on an emergency stop.
+pub async fn execute(
+    plan: CoordinatedPlan,
+    x_pin: LocalGpioConfig,
+    y_pin: LocalGpioConfig,
+) -> anyhow::Result<PulseReport> {
+    submit(plan.steps, x_pin, Some(y_pin)).await
+}
+
+/// Same as [`execute`] for a move of the axis stepped by `pin` alone.
+pub async fn execute_axis(plan: AxisPlan, pin: LocalGpioConfig) -> anyhow::Result<PulseReport> {
+    let steps = plan
+        .intervals
+        .into_iter()
+        .map(|interval| PlannedStep {
+            x: true,
+            y: false,
+            interval,
+        })
+        .collect();
+    submit(steps, pin, None).await
+}
+
+async fn submit(
+    steps: Vec<PlannedStep>,
+    x_pin: LocalGpioConfig,
+    y_pin: Option<LocalGpioConfig>,
+) -> anyhow::Result<PulseReport> {
+    let (done, finished) = oneshot::channel();
+    PULSE_THREAD
+        .lock()
+        .unwrap()
+        .send(PulseJob {
+            steps,
+            x_pin,
+            y_pin,
+            done,
+        })
+        .map_err(|_| anyhow!("step pulse thread stopped"))?;
+    finished
+        .await
+        .map_err(|_| anyhow!("step pulse thread dropped the move"))?
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    estop,
+    linear::AxisConfig,
+    planner::{plan_axis, plan_xy, CoordinatedPlan},
+    Dose, MotionBackend, WATER,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimEventKind {
+    Step { axis: Axis, decreasing: bool },
+    Arrived { x: u32, y: u32 },
+    ArrivedZ { z: u32 },
+    Homed,
+    Pump { channel: String, on: bool },
+    Aborted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimEvent {
+    pub at: Duration,
+    pub kind: SimEventKind,
+}
+
+/// Gantry without hardware. Moves are planned exactly like the GPIO backend
+/// but the pulses advance a virtual clock and are recorded in a bounded log
+/// instead of toggling pins.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct SimulatorConfig {
+    pub linear_x: AxisConfig,
+    pub linear_y: AxisConfig,
+    #[serde(default)]
+    pub linear_z: Option<AxisConfig>,
+    pub realtime: bool,
+    pub log_capacity: usize,
+    /// Pump flow reported by a simulated flow meter, none without one.
+    #[serde(default)]
+    pub flow_ml_per_s: Option<f64>,
+    /// Additive channels by name with their pump rate in ml/s.
+    #[serde(default)]
+    pub dosing: BTreeMap<String, f64>,
+    #[serde(skip)]
+    clock: Duration,
+    #[serde(skip)]
+    log: VecDeque<SimEvent>,
+    /// Clock reading when the running pump was switched on.
+    #[serde(skip)]
+    pump_started: Option<Duration>,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            linear_x: Default::default(),
+            linear_y: Default::default(),
+            linear_z: None,
+            realtime: true,
+            log_capacity: 100_000,
+            flow_ml_per_s: None,
+            dosing: BTreeMap::new(),
+            clock: Duration::ZERO,
+            log: VecDeque::new(),
+            pump_started: None,
+        }
+    }
+}
+
+impl SimulatorConfig {
+    fn record(&mut self, at: Duration, kind: SimEventKind) {
+        if self.log.len() >= self.log_capacity {
+            self.log.pop_front();
+        }
+        self.log.push_back(SimEvent { at, kind });
+    }
+
+    fn record_steps(&mut self, plan: &CoordinatedPlan, ticks: usize, start: Duration) {
+        let mut at = start;
+        for step in plan.steps.iter().take(ticks) {
+            if step.x {
+                let decreasing = plan.x_decreasing;
+                self.record(
+                    at,
+                    SimEventKind::Step {
+                        axis: Axis::X,
+                        decreasing,
+                    },
+                );
+            }
+            if step.y {
+                let decreasing = plan.y_decreasing;
+                self.record(
+                    at,
+                    SimEventKind::Step {
+                        axis: Axis::Y,
+                        decreasing,
+                    },
+                );
+            }
+            at += step.interval;
+        }
+    }
+
+    /// Rate of the pump of `channel` in ml/s, when it is measured.
+    fn flow(&self, channel: &str) -> anyhow::Result<Option<f64>> {
+        if channel == WATER {
+            return Ok(self.flow_ml_per_s);
+        }
+        let rate = self.dosing.get(channel);
+        Ok(Some(*rate.ok_or_else(|| {
+            anyhow!("unknown dosing channel {channel}")
+        })?))
+    }
+
+    /// Advances the virtual clock by `dur`, or by less if an emergency stop
+    /// interrupts a real-time run. Returns the time actually advanced.
+    async fn advance(&mut self, dur: Duration) -> Duration {
+        let elapsed = if self.realtime {
+            let started = Instant::now();
+            match estop::sleep_unless_halted(dur).await {
+                Ok(()) => dur,
+                Err(_) => started.elapsed().min(dur),
+            }
+        } else {
+            dur
+        };
+        self.clock += elapsed;
+        elapsed
+    }
+}
+
+impl MotionBackend for SimulatorConfig {
+    async fn home(&mut self) -> anyhow::Result<()> {
+        if self.linear_z.is_some() {
+            self.goto_z(0).await?;
+        }
+        self.goto(0, 0).await?;
+        self.record(self.clock, SimEventKind::Homed);
+        Ok(())
+    }
+
+    async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        // Same direction setup delay as the GPIO axes.
+        let setup = Duration::from_millis(1);
+        let start = self.clock + setup;
+        let plan = plan_xy(&self.linear_x, x, &self.linear_y, y);
+
+        let elapsed = self.advance(setup + plan.duration()).await;
+        let ticks = plan.ticks_within(elapsed.saturating_sub(setup));
+        self.record_steps(&plan, ticks, start);
+        if ticks < plan.steps.len() || estop::is_halted() {
+            let (x_pulses, y_pulses) = plan.pulses(ticks);
+            self.linear_x.stopped_after(x_pulses, plan.x_decreasing);
+            self.linear_y.stopped_after(y_pulses, plan.y_decreasing);
+            self.record(self.clock, SimEventKind::Aborted);
+            bail!("move aborted by emergency stop");
+        }
+        self.linear_x.position = x;
+        self.linear_y.position = y;
+        self.record(self.clock, SimEventKind::Arrived { x, y });
+        log::debug!("Simulated move to ({x}, {y}) took {elapsed:?}");
+        Ok(())
+    }
+
+    async fn goto_z(&mut self, z: u32) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        let Some(mut axis) = self.linear_z.clone() else {
+            bail!("no z axis configured");
+        };
+        let setup = Duration::from_millis(1);
+        let start = self.clock + setup;
+        let plan = plan_axis(&axis, z);
+
+        let elapsed = self.advance(setup + plan.duration()).await;
+        let ticks = plan.ticks_within(elapsed.saturating_sub(setup));
+        let mut at = start;
+        for interval in plan.intervals.iter().take(ticks) {
+            self.record(
+                at,
+                SimEventKind::Step {
+                    axis: Axis::Z,
+                    decreasing: plan.decreasing,
+                },
+            );
+            at += *interval;
+        }
+        if ticks < plan.intervals.len() || estop::is_halted() {
+            axis.stopped_after(ticks as u32, plan.decreasing);
+            self.linear_z.replace(axis);
+            self.record(self.clock, SimEventKind::Aborted);
+            bail!("move aborted by emergency stop");
+        }
+        axis.position = z;
+        self.linear_z.replace(axis);
+        self.record(self.clock, SimEventKind::ArrivedZ { z });
+        Ok(())
+    }
+
+    async fn start_pump(&mut self, channel: &str) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        self.flow(channel)?;
+        self.record(
+            self.clock,
+            SimEventKind::Pump {
+                channel: channel.to_owned(),
+                on: true,
+            },
+        );
+        self.pump_started = Some(self.clock);
+        Ok(())
+    }
+
+    async fn pump_until(&mut self, channel: &str, dose: Dose) -> anyhow::Result<()> {
+        let Some(since) = self.pump_started else {
+            bail!("pump not running");
+        };
+        let dur = match (dose, self.flow(channel)?) {
+            (Dose::Duration(dur), _) => dur,
+            (Dose::Volume { ml, max }, Some(rate)) => {
+                Duration::from_secs_f64(ml / rate.max(f64::EPSILON)).min(max)
+            }
+            (Dose::Volume { .. }, None) => bail!("no flow meter configured"),
+        };
+        let wait = (since + dur).saturating_sub(self.clock);
+        if self.advance(wait).await < wait {
+            self.record(self.clock, SimEventKind::Aborted);
+            bail!("watering aborted by emergency stop");
+        }
+        Ok(())
+    }
+
+    async fn stop_pump(&mut self, channel: &str) -> anyhow::Result<Option<f64>> {
+        let flow = self.flow(channel)?;
+        let Some(since) = self.pump_started.take() else {
+            return Ok(None);
+        };
+        self.record(
+            self.clock,
+            SimEventKind::Pump {
+                channel: channel.to_owned(),
+                on: false,
+            },
+        );
+        let on_for = self.clock - since;
+        Ok(flow.map(|rate| rate * on_for.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulator() -> SimulatorConfig {
+        SimulatorConfig {
+            realtime: false,
+            flow_ml_per_s: Some(10.0),
+            ..Default::default()
+        }
+    }
+
+    fn steps(sim: &SimulatorConfig, axis: Axis, decreasing: bool) -> usize {
+        sim.log()
+            .iter()
+            .filter(|event| event.kind == SimEventKind::Step { axis, decreasing })
+            .count()
+    }
+
+    #[test]
+    fn goto_pulses_each_axis_and_arrives() {
+        let mut sim = simulator();
+        async_std::task::block_on(sim.goto(10, 5)).unwrap();
+        assert_eq!(steps(&sim, Axis::X, false), 400);
+        assert_eq!(steps(&sim, Axis::Y, false), 200);
+        assert_eq!(
+            sim.log().back().unwrap().kind,
+            SimEventKind::Arrived { x: 10, y: 5 }
+        );
+        assert_eq!((sim.linear_x.position, sim.linear_y.position), (10, 5));
+        assert!(!sim.clock().is_zero());
+
+        async_std::task::block_on(sim.goto(0, 5)).unwrap();
+        assert_eq!(steps(&sim, Axis::X, true), 400);
+        assert_eq!(steps(&sim, Axis::Y, true), 0);
+    }
+
+    #[test]
+    fn pump_runs_until_the_dose_is_delivered() {
+        let mut sim = simulator();
+        let dose = Dose::Volume {
+            ml: 5.0,
+            max: Duration::from_secs(10),
+        };
+        let ml = async_std::task::block_on(async {
+            sim.start_pump(WATER).await?;
+            sim.pump_until(WATER, dose).await?;
+            sim.stop_pump(WATER).await
+        })
+        .unwrap();
+        assert_eq!(ml, Some(5.0));
+        let pump: Vec<_> = sim.log().iter().collect();
+        assert_eq!(pump.len(), 2);
+        assert_eq!(
+            pump[0].kind,
+            SimEventKind::Pump {
+                channel: WATER.to_owned(),
+                on: true
+            }
+        );
+        assert_eq!(
+            pump[1].kind,
+            SimEventKind::Pump {
+                channel: WATER.to_owned(),
+                on: false
+            }
+        );
+        assert_eq!(pump[1].at - pump[0].at, Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,114 @@
+use std::f64::consts::PI;
+
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SprayPattern {
+    /// A single stationary squirt over the middle of the cluster.
+    #[default]
+    Point,
+    /// Concentric rings around the middle of the cluster.
+    Circle,
+    /// Back-and-forth rows covering the bounding box.
+    Raster,
+}
+
+/// Bounding box of the detected cluster in mm: its middle relative to the
+/// position and its size.
+#[derive(Getters, Debug, Clone, Copy, Default)]
+pub struct SprayArea {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SprayConfig {
+    pub pattern: SprayPattern,
+    /// Spacing between rows, rings and waypoints, about the width the
+    /// nozzle wets.
+    pub pitch_mm: f64,
+    /// Extra distance covered around the bounding box.
+    pub margin_mm: f64,
+    pub max_waypoints: usize,
+}
+
+impl Default for SprayConfig {
+    fn default() -> Self {
+        Self {
+            pattern: SprayPattern::Point,
+            pitch_mm: 10.0,
+            margin_mm: 0.0,
+            max_waypoints: 50,
+        }
+    }
+}
+
+impl SprayConfig {
+    /// Offsets in mm from the position the nozzle stops at, each getting an
+    /// equal share of the dose.
+    pub fn waypoints(&self, area: &SprayArea) -> Vec<(f64, f64)> {
+        let pitch = self.pitch_mm.max(1.0);
+        let half_w = (area.width / 2.0 + self.margin_mm).max(0.0);
+        let half_h = (area.height / 2.0 + self.margin_mm).max(0.0);
+        let mut points = match self.pattern {
+            SprayPattern::Point => vec![(0.0, 0.0)],
+            SprayPattern::Circle => {
+                let radius = half_w.max(half_h);
+                let mut points = vec![(0.0, 0.0)];
+                let rings = (radius / pitch).floor() as usize;
+                for ring in 1..=rings {
+                    let r = ring as f64 * pitch;
+                    let n = (2.0 * PI * r / pitch).ceil() as usize;
+                    points.extend((0..n).map(|i| {
+                        let a = 2.0 * PI * i as f64 / n as f64;
+                        (r * a.cos(), r * a.sin())
+                    }));
+                }
+                points
+            }
+            SprayPattern::Raster => {
+                let rows = (2.0 * half_h / pitch).floor() as usize + 1;
+                let cols = (2.0 * half_w / pitch).floor() as usize + 1;
+                let spread = |n: usize, half: f64| -> Vec<f64> {
+                    if n == 1 {
+                        return vec![0.0];
+                    }
+                    (0..n)
+                        .map(|i| -half + 2.0 * half * i as f64 / (n - 1) as f64)
+                        .collect()
+                };
+                let xs = spread(cols, half_w);
+                let mut points = Vec::with_capacity(rows * cols);
+                for (row, y) in spread(rows, half_h).into_iter().enumerate() {
+                    let mut row_xs = xs.clone();
+                    if row % 2 == 1 {
+                        row_xs.reverse();
+                    }
+                    points.extend(row_xs.into_iter().map(|x| (x, y)));
+                }
+                points
+            }
+        };
+        let max = self.max_waypoints.max(1);
+        if points.len() > max {
+            log::warn!(
+                "Spray pattern needs {} waypoints, keeping {max}",
+                points.len()
+            );
+            // Thin out evenly rather than dropping the far side of the area.
+            let step = points.len() as f64 / max as f64;
+            points = (0..max)
+                .map(|i| points[(i as f64 * step) as usize])
+                .collect();
+        }
+        points
+            .into_iter()
+            .map(|(dx, dy)| (area.x + dx, area.y + dy))
+            .collect()
+    }
+}
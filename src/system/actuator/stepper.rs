@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use async_std::task::sleep;
+use derive_getters::Getters;
+use futures::future::join;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    gpio_pin::LocalGpioConfig,
+    idle::{self, DriversBusy, IdleConfig},
+    linear::LocalLinearConfig,
+    planner::{plan_axis, plan_axis_steps, plan_steps, plan_xy, AxisPlan, CoordinatedPlan},
+    pulse::{self, PulseReport},
+    watering::WateringConfig,
+    Dose, MotionBackend, WATER,
+};
+
+#[derive(Getters, Serialize, Deserialize, Clone, Default)]
+pub struct GpioStepperConfig {
+    pub en_pin: LocalGpioConfig,
+    pub linear_x: LocalLinearConfig,
+    pub linear_y: LocalLinearConfig,
+    /// Raises and lowers the camera and nozzle; homes to its raised end.
+    #[serde(default)]
+    pub linear_z: Option<LocalLinearConfig>,
+    pub watering: WateringConfig,
+    #[serde(default)]
+    pub idle: IdleConfig,
+    /// Additive channels by name, each with its own pump.
+    #[serde(default)]
+    pub dosing: BTreeMap<String, WateringConfig>,
+}
+
+fn signed(steps: u32, decreasing: bool) -> i64 {
+    if decreasing {
+        -(steps as i64)
+    } else {
+        steps as i64
+    }
+}
+
+/// Runs the move, marking both axes unhomed when it fails part way, as some
+/// steps may already have been emitted.
+async fn run(
+    plan: CoordinatedPlan,
+    linear_x: &mut LocalLinearConfig,
+    linear_y: &mut LocalLinearConfig,
+) -> anyhow::Result<PulseReport> {
+    let (x_pin, y_pin) = (linear_x.step_pin.clone(), linear_y.step_pin.clone());
+    let res = pulse::execute(plan, x_pin, y_pin).await;
+    if res.is_err() {
+        linear_x.forget_position();
+        linear_y.forget_position();
+    }
+    log_jitter(res)
+}
+
+/// Same as [`run`] for a single axis.
+async fn run_axis(plan: AxisPlan, linear: &mut LocalLinearConfig) -> anyhow::Result<PulseReport> {
+    let res = pulse::execute_axis(plan, linear.step_pin.clone()).await;
+    if res.is_err() {
+        linear.forget_position();
+    }
+    log_jitter(res)
+}
+
+fn log_jitter(res: anyhow::Result<PulseReport>) -> anyhow::Result<PulseReport> {
+    let report = res?;
+    let jitter = &report.jitter;
+    log::info!(
+        "Step timing: {} pulses, mean late {:?}, max late {:?}, {} over 100us",
+        jitter.pulses,
+        jitter.mean,
+        jitter.max,
+        jitter.over_100us
+    );
+    Ok(report)
+}
+
+impl GpioStepperConfig {
+    fn pump(&mut self, channel: &str) -> anyhow::Result<&mut WateringConfig> {
+        if channel == WATER {
+            return Ok(&mut self.watering);
+        }
+        self.dosing
+            .get_mut(channel)
+            .ok_or_else(|| anyhow!("unknown dosing channel {channel}"))
+    }
+
+    /// Carriage position the move to `(x, y)` detours through so that each
+    /// axis arrives from its configured side, if any axis needs it.
+    pub fn approach_via(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let via_x = self.linear_x.approach_via(x);
+        let via_y = self.linear_y.approach_via(y);
+        (via_x.is_some() || via_y.is_some()).then(|| (via_x.unwrap_or(x), via_y.unwrap_or(y)))
+    }
+
+    /// Homes Z first so that the carriage travels raised.
+    async fn home_axes(&mut self) -> anyhow::Result<()> {
+        if let Some(linear_z) = self.linear_z.as_mut() {
+            linear_z.home().await?;
+        }
+        let (x_res, y_res) = join(self.linear_x.home(), self.linear_y.home()).await;
+        x_res?;
+        y_res?;
+        Ok(())
+    }
+
+    /// Enables the drivers for a move, homing again if the carriage may
+    /// have moved while they were off.
+    async fn wake(&mut self) -> anyhow::Result<DriversBusy> {
+        let (busy, was_off) = idle::acquire(&self.en_pin, &self.idle).await?;
+        if was_off && !self.idle.holds_position {
+            let (mut rehome, mut assumed) = (false, false);
+            let axes = [Some(&mut self.linear_x), Some(&mut self.linear_y)];
+            for axis in axes.into_iter().chain([self.linear_z.as_mut()]).flatten() {
+                if axis.forget_position() {
+                    rehome = true;
+                } else {
+                    assumed = true;
+                }
+            }
+            if rehome {
+                log::info!("Stepper drivers were disabled, homing again");
+                self.home_axes().await?;
+            }
+            if assumed {
+                log::warn!("Stepper drivers were disabled, assuming unhomed axes did not move");
+            }
+        }
+        Ok(busy)
+    }
+
+    /// Straight move to `(x, y)`. Axes that reverse first take up their
+    /// backlash without the carriage moving.
+    async fn move_to(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        let plan = plan_xy(&self.linear_x.axis, x, &self.linear_y.axis, y);
+        let (x_decreasing, y_decreasing) = (plan.x_decreasing, plan.y_decreasing);
+        let x_moves = plan.steps.iter().any(|step| step.x);
+        let y_moves = plan.steps.iter().any(|step| step.y);
+        if !x_moves && !y_moves {
+            return Ok(());
+        }
+
+        self.linear_x.set_direction(x_decreasing)?;
+        self.linear_y.set_direction(y_decreasing)?;
+        sleep(Duration::from_millis(1)).await;
+
+        let x_slack = x_moves as u32 * self.linear_x.backlash_steps(x_decreasing);
+        let y_slack = y_moves as u32 * self.linear_y.backlash_steps(y_decreasing);
+        if x_slack > 0 || y_slack > 0 {
+            let takeup = plan_steps(
+                &self.linear_x.axis,
+                signed(x_slack, x_decreasing),
+                &self.linear_y.axis,
+                signed(y_slack, y_decreasing),
+            );
+            if run(takeup, &mut self.linear_x, &mut self.linear_y)
+                .await?
+                .aborted
+            {
+                self.linear_x.stopped_after(0, x_decreasing);
+                self.linear_y.stopped_after(0, y_decreasing);
+                bail!("move aborted by emergency stop");
+            }
+        }
+        if x_moves {
+            self.linear_x.moved(x_decreasing);
+        }
+        if y_moves {
+            self.linear_y.moved(y_decreasing);
+        }
+
+        let report = run(plan, &mut self.linear_x, &mut self.linear_y).await?;
+        if report.aborted {
+            self.linear_x.stopped_after(report.x_pulses, x_decreasing);
+            self.linear_y.stopped_after(report.y_pulses, y_decreasing);
+            bail!("move aborted by emergency stop");
+        }
+        self.linear_x.axis.position = x;
+        self.linear_y.axis.position = y;
+        Ok(())
+    }
+}
+
+impl MotionBackend for GpioStepperConfig {
+    async fn home(&mut self) -> anyhow::Result<()> {
+        let (_busy, _) = idle::acquire(&self.en_pin, &self.idle).await?;
+        self.home_axes().await
+    }
+
+    async fn goto(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        let _busy = self.wake().await?;
+        if !self.linear_x.position_known() || !self.linear_y.position_known() {
+            bail!("axis position unknown, homing required");
+        }
+        for (axis, linear, target) in [('x', &self.linear_x, x), ('y', &self.linear_y, y)] {
+            let Some(via) = linear.approach_via(target) else {
+                continue;
+            };
+            if via.abs_diff(target) < linear.overshoot() {
+                log::warn!(
+                    "Approach to {axis} = {target} mm overshoots by only {} mm at the end of \
+                     the travel, backlash may remain",
+                    via.abs_diff(target)
+                );
+            }
+        }
+        if let Some((via_x, via_y)) = self.approach_via(x, y) {
+            self.move_to(via_x, via_y).await?;
+        }
+        self.move_to(x, y).await
+    }
+
+    async fn goto_z(&mut self, z: u32) -> anyhow::Result<()> {
+        if self.linear_z.is_none() {
+            bail!("no z axis configured");
+        }
+        let _busy = self.wake().await?;
+        let linear_z = self
+            .linear_z
+            .as_mut()
+            .ok_or_else(|| anyhow!("no z axis configured"))?;
+        if !linear_z.position_known() {
+            bail!("z position unknown, homing required");
+        }
+        let plan = plan_axis(&linear_z.axis, z);
+        if plan.intervals.is_empty() {
+            return Ok(());
+        }
+        let decreasing = plan.decreasing;
+        linear_z.set_direction(decreasing)?;
+        sleep(Duration::from_millis(1)).await;
+
+        let slack = linear_z.backlash_steps(decreasing);
+        if slack > 0 {
+            let takeup = plan_axis_steps(&linear_z.axis, signed(slack, decreasing));
+            if run_axis(takeup, linear_z).await?.aborted {
+                linear_z.stopped_after(0, decreasing);
+                bail!("move aborted by emergency stop");
+            }
+        }
+        linear_z.moved(decreasing);
+
+        let report = run_axis(plan, linear_z).await?;
+        if report.aborted {
+            linear_z.stopped_after(report.x_pulses, decreasing);
+            bail!("move aborted by emergency stop");
+        }
+        linear_z.axis.position = z;
+        Ok(())
+    }
+
+    async fn start_pump(&mut self, channel: &str) -> anyhow::Result<()> {
+        self.pump(channel)?.start()
+    }
+
+    async fn pump_until(&mut self, channel: &str, dose: Dose) -> anyhow::Result<()> {
+        self.pump(channel)?.pump_until(dose).await
+    }
+
+    async fn stop_pump(&mut self, channel: &str) -> anyhow::Result<Option<f64>> {
+        self.pump(channel)?.stop().await
+    }
+}
@@ -0,0 +1,19 @@
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Tool whose image the automation analyses.
+pub const CAMERA: &str = "camera";
+/// Tool the pump and dosing channels deliver through.
+pub const NOZZLE: &str = "nozzle";
+
+/// Where a tool sits relative to the carriage reference point, in mm. `z` is
+/// how far below the carriage the tool reaches.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ToolOffset {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    #[serde(default)]
+    pub z: i32,
+}
@@ -0,0 +1,257 @@
+use std::os::fd::{AsRawFd, RawFd};
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::gpio_pin::{self, LocalGpioConfig};
+
+/// How many outputs the watchdog can guard.
+const CAPACITY: usize = 64;
+/// `GPIOHANDLE_SET_LINE_VALUES_IOCTL`: `_IOWR(0xB4, 0x09, struct
+/// gpiohandle_data)`.
+const SET_LINE_VALUES: u32 = 0xC040_B409;
+
+const EMPTY: u8 = 0;
+const GPIO_OFF_LOW: u8 = 1;
+const GPIO_OFF_HIGH: u8 = 2;
+/// GRBL serial port, stopped with a soft reset, which also switches the
+/// spindle and coolant outputs off.
+const GRBL: u8 = 3;
+const GRBL_NAME: &str = "grbl";
+
+/// Output the watchdog switches off on its own: a duplicate of the line or
+/// port descriptor, so that doing so takes no lock another thread may hold.
+struct Slot {
+    fd: AtomicI32,
+    kind: AtomicU8,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            fd: AtomicI32::new(-1),
+            kind: AtomicU8::new(EMPTY),
+        }
+    }
+
+    fn switch_off(&self) {
+        let fd = self.fd.load(Ordering::SeqCst);
+        if fd < 0 {
+            return;
+        }
+        match self.kind.load(Ordering::SeqCst) {
+            kind @ (GPIO_OFF_LOW | GPIO_OFF_HIGH) => {
+                let mut values = [0u8; 64];
+                values[0] = (kind == GPIO_OFF_HIGH) as u8;
+                // SAFETY: `values` is the `struct gpiohandle_data` the request
+                // reads, and `fd` is a line handle we own.
+                unsafe { libc::ioctl(fd, SET_LINE_VALUES as _, values.as_mut_ptr()) };
+            }
+            GRBL => {
+                let reset = 0x18u8;
+                // SAFETY: writes one byte from a valid buffer to our own port.
+                unsafe { libc::write(fd, &reset as *const u8 as *const libc::c_void, 1) };
+            }
+            _ => (),
+        }
+    }
+}
+
+static SLOTS: [Slot; CAPACITY] = [const { Slot::new() }; CAPACITY];
+/// Name of the output held by each slot, only needed to register and arm.
+static NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Outputs currently on, with the instant they must be off by.
+static ARMED: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// A panic elsewhere must not stop the pumps from being switched off.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn name(pin: &LocalGpioConfig) -> String {
+    format!("{}:{}", pin.chip.display(), pin.line)
+}
+
+/// Puts `fd` in the slot named `name`, replacing what it held, and returns
+/// that slot. Takes ownership of `fd`.
+fn register(name: String, fd: RawFd, kind: u8) -> anyhow::Result<&'static Slot> {
+    let mut names = lock(&NAMES);
+    let index = match names.iter().position(|n| *n == name) {
+        Some(index) => index,
+        None if names.len() < CAPACITY => {
+            names.push(name);
+            names.len() - 1
+        }
+        None => {
+            // SAFETY: `fd` is ours and not stored anywhere.
+            unsafe { libc::close(fd) };
+            anyhow::bail!("the watchdog guards at most {CAPACITY} outputs");
+        }
+    };
+    let slot = &SLOTS[index];
+    slot.kind.store(kind, Ordering::SeqCst);
+    let old = slot.fd.swap(fd, Ordering::SeqCst);
+    if old >= 0 {
+        // SAFETY: the slot owned `old` and no longer refers to it.
+        unsafe { libc::close(old) };
+    }
+    Ok(slot)
+}
+
+/// Lets the watchdog switch `pin` off on shutdown, on panic and when armed
+/// for too long. `off_high` gives the level that switches it off.
+pub fn guard_output(pin: &LocalGpioConfig, off_high: bool) -> anyhow::Result<()> {
+    if pin.chip == Path::new("stub") {
+        return Ok(());
+    }
+    let fd = gpio_pin::output_fd(pin)?;
+    let kind = if off_high {
+        GPIO_OFF_HIGH
+    } else {
+        GPIO_OFF_LOW
+    };
+    register(name(pin), fd, kind)?.switch_off();
+    Ok(())
+}
+
+/// Lets the watchdog soft-reset the GRBL controller on `port`, stopping its
+/// motion, spindle and coolant.
+pub fn guard_grbl(port: &impl AsRawFd) -> anyhow::Result<()> {
+    // SAFETY: dup(2) on a descriptor borrowed for the call.
+    let fd = unsafe { libc::dup(port.as_raw_fd()) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    register(GRBL_NAME.to_owned(), fd, GRBL)?;
+    Ok(())
+}
+
+/// Switches every guarded output off. Takes no lock, so it is safe from the
+/// panic hook whatever the panicking thread held.
+pub fn force_all_off() {
+    for slot in &SLOTS {
+        slot.switch_off();
+    }
+}
+
+/// Registers the pump outputs, switches them off and starts the watchdog
+/// thread, the panic hook and the signal handler.
+pub fn install(outputs: Vec<LocalGpioConfig>) {
+    for pin in &outputs {
+        if let Err(e) = guard_output(pin, false) {
+            log::error!("Pump {:?} is not guarded by the watchdog: {e}", pin);
+        }
+    }
+    INSTALL.call_once(|| {
+        thread::Builder::new()
+            .name("pump-watchdog".to_owned())
+            .spawn(watch)
+            .expect("unable to spawn pump watchdog thread");
+
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            force_all_off();
+            default_hook(info);
+        }));
+
+        watch_signals();
+    });
+}
+
+/// Allows `pin` to stay on for at most `max_on`, whatever happens to the
+/// task that switched it on.
+pub fn arm(pin: &LocalGpioConfig, max_on: Duration) {
+    arm_slot(name(pin), max_on);
+}
+
+pub fn disarm(pin: &LocalGpioConfig) {
+    disarm_slot(&name(pin));
+}
+
+/// Soft-resets the GRBL controller unless [`disarm_grbl`] is called within
+/// `max_on`, stopping a pump or coolant it switched on.
+pub fn arm_grbl(max_on: Duration) {
+    arm_slot(GRBL_NAME.to_owned(), max_on);
+}
+
+pub fn disarm_grbl() {
+    disarm_slot(GRBL_NAME);
+}
+
+fn arm_slot(name: String, max_on: Duration) {
+    let mut armed = lock(&ARMED);
+    armed.retain(|(armed, _)| *armed != name);
+    armed.push((name, Instant::now() + max_on));
+}
+
+fn disarm_slot(name: &str) {
+    lock(&ARMED).retain(|(armed, _)| armed != name);
+}
+
+fn watch() {
+    loop {
+        let now = Instant::now();
+        let expired: Vec<_> = {
+            let mut armed = lock(&ARMED);
+            let (expired, running) = armed.drain(..).partition(|(_, deadline)| *deadline <= now);
+            *armed = running;
+            expired
+        };
+        for (name, _) in expired {
+            log::error!("Pump {name} exceeded its maximum on-time, forcing it off");
+            if let Some(index) = lock(&NAMES).iter().position(|n| *n == name) {
+                SLOTS[index].switch_off();
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = signal as u8;
+        // SAFETY: write(2) is async-signal-safe and the buffer outlives the
+        // call.
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// Switches the pumps off before exiting on SIGINT, SIGTERM or SIGHUP. The
+/// handler only writes to a pipe; the work happens on a regular thread.
+fn watch_signals() {
+    let mut fds = [0 as libc::c_int; 2];
+    // SAFETY: `fds` has room for the two descriptors pipe(2) returns.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        log::warn!(
+            "Pumps are not switched off on shutdown: {}",
+            std::io::Error::last_os_error()
+        );
+        return;
+    }
+    SIGNAL_PIPE.store(fds[1], Ordering::SeqCst);
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: `on_signal` only does async-signal-safe work.
+        unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+    }
+
+    let read_fd = fds[0];
+    thread::Builder::new()
+        .name("shutdown".to_owned())
+        .spawn(move || {
+            let mut byte = 0u8;
+            // SAFETY: reads one byte into a valid buffer from our own pipe.
+            let n = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if n == 1 {
+                log::warn!("Received signal {byte}, switching pumps off");
+                force_all_off();
+                std::process::exit(128 + byte as i32);
+            }
+        })
+        .expect("unable to spawn shutdown thread");
+}
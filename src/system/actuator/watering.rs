@@ -1,20 +1,153 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use anyhow::bail;
+use async_std::task::sleep;
 use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
 
-use super::gpio_pin::LocalGpioConfig;
+use super::{estop, flow::FlowMeterConfig, gpio_pin::LocalGpioConfig, watchdog, Dose};
 
-#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
 pub struct WateringConfig {
     pub pin: LocalGpioConfig,
+    #[serde(default)]
+    pub flow_meter: Option<FlowMeterConfig>,
+    /// Calibrated rate of a metering pump, used to dose by volume without a
+    /// flow meter.
+    #[serde(default)]
+    pub ml_per_s: Option<f64>,
+    /// Hard limit after which the watchdog switches the pump off, whatever
+    /// the dose asked for.
+    #[serde(default = "default_max_on_s")]
+    pub max_on_s: u64,
+    #[serde(skip)]
+    running: Option<Running>,
+}
+
+fn default_max_on_s() -> u64 {
+    300
+}
+
+impl Default for WateringConfig {
+    fn default() -> Self {
+        Self {
+            pin: Default::default(),
+            flow_meter: None,
+            ml_per_s: None,
+            max_on_s: default_max_on_s(),
+            running: None,
+        }
+    }
+}
+
+/// Switches the pump off when dropped, so a cancelled or failed watering
+/// never leaves it running.
+#[derive(Debug)]
+struct PumpGuard(LocalGpioConfig);
+
+impl Drop for PumpGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.set_low() {
+            log::error!("Unable to switch the pump off: {e}");
+        }
+        watchdog::disarm(&self.0);
+    }
+}
+
+/// Pump switched on by [`WateringConfig::start`], with the flow meter count
+/// at that time.
+#[derive(Debug, Clone)]
+struct Running {
+    since: Instant,
+    pulses: u64,
+    _guard: Arc<PumpGuard>,
 }
 
 impl WateringConfig {
-    pub async fn water(&mut self, dur: Duration) -> anyhow::Result<()> {
+    /// Lines used by the pump and its flow meter, named after `channel`.
+    pub fn gpio_lines(&self, channel: &str) -> Vec<(String, LocalGpioConfig)> {
+        let mut lines = vec![(channel.to_owned(), self.pin.clone())];
+        if let Some(meter) = &self.flow_meter {
+            lines.push((format!("{channel}:flow"), meter.pin.clone()));
+        }
+        lines
+    }
+
+    pub fn measures_flow(&self) -> bool {
+        self.flow_meter.is_some() || self.ml_per_s.is_some()
+    }
+
+    /// Switches the pump on and leaves it running until [`Self::stop`].
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        estop::ensure_running()?;
+        if self.running.is_some() {
+            return Ok(());
+        }
+        let pulses = match &self.flow_meter {
+            Some(meter) => meter.pulses()?,
+            None => 0,
+        };
+        let guard = PumpGuard(self.pin.clone());
+        watchdog::arm(&self.pin, Duration::from_secs(self.max_on_s));
         self.pin.set_high()?;
-        async_std::task::sleep(dur).await;
-        self.pin.set_low()?;
+        self.running = Some(Running {
+            since: Instant::now(),
+            pulses,
+            _guard: Arc::new(guard),
+        });
         Ok(())
     }
+
+    /// Keeps the running pump on until `dose` has been delivered since
+    /// [`Self::start`], as counted by the flow meter or timed from the
+    /// calibrated rate.
+    pub async fn pump_until(&self, dose: Dose) -> anyhow::Result<()> {
+        let Some(running) = &self.running else {
+            bail!("pump not running");
+        };
+        let until = |dur: Duration| (running.since + dur).saturating_duration_since(Instant::now());
+        let (ml, max, meter) = match (dose, &self.flow_meter, self.ml_per_s) {
+            (Dose::Duration(dur), _, _) => return estop::sleep_unless_halted(until(dur)).await,
+            (Dose::Volume { ml, max }, Some(meter), _) => (ml, max, meter),
+            (Dose::Volume { ml, max }, None, Some(rate)) => {
+                let dur = Duration::from_secs_f64(ml / rate.max(f64::EPSILON)).min(max);
+                return estop::sleep_unless_halted(until(dur)).await;
+            }
+            (Dose::Volume { .. }, None, None) => bail!("no flow meter configured"),
+        };
+        let deadline = running.since + max;
+        loop {
+            let delivered = meter.ml(meter.pulses()? - running.pulses);
+            if delivered >= ml {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                log::warn!(
+                    "Watering cut off after {max:?} with {delivered:.0} of {ml:.0} ml delivered"
+                );
+                return Ok(());
+            }
+            estop::sleep_unless_halted(Duration::from_millis(10)).await?;
+        }
+    }
+
+    /// Switches the pump off and returns the volume delivered since
+    /// [`Self::start`], counted by the flow meter once the line has drained,
+    /// or estimated from the calibrated rate.
+    pub async fn stop(&mut self) -> anyhow::Result<Option<f64>> {
+        let Some(Running { since, pulses, .. }) = self.running.take() else {
+            return Ok(None);
+        };
+        let on_for = since.elapsed();
+
+        let Some(meter) = &self.flow_meter else {
+            return Ok(self.ml_per_s.map(|rate| rate * on_for.as_secs_f64()));
+        };
+        // Let the line drain so the last pulses are counted.
+        sleep(Duration::from_millis(500)).await;
+        let delivered = meter.ml(meter.pulses()? - pulses);
+        log::info!("Delivered {delivered:.0} ml");
+        Ok(Some(delivered))
+    }
 }
@@ -8,11 +8,89 @@ use v4l::io::traits::CaptureStream as _;
 use v4l::video::Capture;
 use v4l::{prelude::*, Format, FourCC};
 
+mod calibration;
+
+pub use calibration::{locate_fiducial, Calibration, CalibrationConfig, PixelTransform, Sample};
+
+/// Size of the frames the camera is set to capture.
+pub const FRAME_WIDTH: u32 = 1280;
+pub const FRAME_HEIGHT: u32 = 720;
+
+/// Bed area, in mm, covered by the centred square the detector sees.
+pub fn field_of_view(transform: PixelTransform) -> (f64, f64) {
+    let edge = FRAME_WIDTH.min(FRAME_HEIGHT) as f64;
+    transform.extent(edge, edge)
+}
+
 #[derive(Getters, Serialize, Deserialize)]
 pub struct CameraConfig {
     #[serde(skip_serializing, skip_deserializing)]
     pub video: Option<UserptrStream>,
     pub video_path: PathBuf,
+    /// Size of a pixel on the bed at the capture height, used to turn
+    /// detections into millimetres.
+    #[serde(default)]
+    pub mm_per_px: Option<f64>,
+    /// Fiducial to solve the pixel to millimetre transform against;
+    /// supersedes `mm_per_px` once solved.
+    #[serde(default)]
+    pub calibration: Option<CalibrationConfig>,
+    /// Moves positions onto the detection they see, when set.
+    #[serde(default)]
+    pub centering: Option<CenteringConfig>,
+    #[serde(default)]
+    pub survey: SurveyConfig,
+}
+
+/// Auto-centering of positions on their detection during checks.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct CenteringConfig {
+    /// Detections closer than this to the image centre are left alone.
+    #[serde(default = "default_tolerance_mm")]
+    pub tolerance_mm: f64,
+    /// How far a position may wander from where it was created, per axis.
+    #[serde(default = "default_max_drift_mm")]
+    pub max_drift_mm: u32,
+}
+
+/// Whole-bed scan proposing positions from what the detector finds.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct SurveyConfig {
+    /// Share of each frame covered by the next one.
+    #[serde(default = "default_overlap")]
+    pub overlap: f64,
+    /// Detections closer than this are taken for the same plant.
+    #[serde(default = "default_merge_mm")]
+    pub merge_mm: f64,
+    /// Height to capture at, also given to approved positions.
+    #[serde(default)]
+    pub capture_z: Option<u32>,
+}
+
+impl Default for SurveyConfig {
+    fn default() -> Self {
+        Self {
+            overlap: default_overlap(),
+            merge_mm: default_merge_mm(),
+            capture_z: None,
+        }
+    }
+}
+
+fn default_overlap() -> f64 {
+    0.2
+}
+
+fn default_merge_mm() -> f64 {
+    30.0
+}
+
+fn default_tolerance_mm() -> f64 {
+    2.0
+}
+
+fn default_max_drift_mm() -> u32 {
+    20
 }
 
 impl Clone for CameraConfig {
@@ -20,6 +98,10 @@ impl Clone for CameraConfig {
         CameraConfig {
             video: None,
             video_path: self.video_path.clone(),
+            mm_per_px: self.mm_per_px,
+            calibration: self.calibration.clone(),
+            centering: self.centering.clone(),
+            survey: self.survey.clone(),
         }
     }
 }
@@ -29,6 +111,10 @@ impl Default for CameraConfig {
         Self {
             video: None,
             video_path: "/dev/video0".into(),
+            mm_per_px: None,
+            calibration: None,
+            centering: None,
+            survey: SurveyConfig::default(),
         }
     }
 }
@@ -37,7 +123,11 @@ impl CameraConfig {
     pub async fn capture_raw(&mut self) -> anyhow::Result<Vec<u8>> {
         if self.video.is_none() {
             let dev = Device::with_path(&self.video_path)?;
-            dev.set_format(&Format::new(1280, 720, FourCC::new(b"MJPG")))?;
+            dev.set_format(&Format::new(
+                FRAME_WIDTH,
+                FRAME_HEIGHT,
+                FourCC::new(b"MJPG"),
+            ))?;
             let stream = UserptrStream::with_buffers(&dev, Type::VideoCapture, 1)?;
             self.video.replace(stream);
         }
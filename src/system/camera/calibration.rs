@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::bail;
+use derive_getters::Getters;
+use image::{DynamicImage, Luma};
+use imageproc::contrast::{otsu_level, threshold, ThresholdType};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use serde::{Deserialize, Serialize};
+
+use crate::database::CameraCalibrationData;
+
+/// Printed fiducial the camera is calibrated against: a solid dark square on
+/// a light background, or any pattern whose dark part is one blob.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct CalibrationConfig {
+    /// Bed position of the fiducial, in mm.
+    pub fiducial_x: u32,
+    pub fiducial_y: u32,
+    /// How far the camera steps away from the fiducial between captures.
+    #[serde(default = "default_step_mm")]
+    pub step_mm: u32,
+    /// Height to calibrate at; the scale only holds at that height.
+    #[serde(default)]
+    pub capture_z: Option<u32>,
+}
+
+fn default_step_mm() -> u32 {
+    20
+}
+
+impl CalibrationConfig {
+    /// Camera positions to capture from: over the fiducial, then a step
+    /// away along each axis.
+    pub fn targets(&self) -> Vec<(u32, u32)> {
+        let (x, y, step) = (self.fiducial_x, self.fiducial_y, self.step_mm);
+        vec![
+            (x, y),
+            (x + step, y),
+            (x.saturating_sub(step), y),
+            (x, y + step),
+            (x, y.saturating_sub(step)),
+        ]
+    }
+}
+
+/// Linear map from a pixel offset relative to the image centre to a bed
+/// offset in mm. It carries scale, rotation and a possible mirroring of the
+/// image axes.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelTransform {
+    m: [[f64; 2]; 2],
+}
+
+impl PixelTransform {
+    /// Uniform scale with the image axes along the gantry axes.
+    pub fn scale(mm_per_px: f64) -> Self {
+        Self {
+            m: [[mm_per_px, 0.0], [0.0, mm_per_px]],
+        }
+    }
+
+    pub fn to_mm(self, dx: f64, dy: f64) -> (f64, f64) {
+        let m = &self.m;
+        (m[0][0] * dx + m[0][1] * dy, m[1][0] * dx + m[1][1] * dy)
+    }
+
+    /// Size on the bed of the box enclosing a `width` by `height` pixel box.
+    pub fn extent(&self, width: f64, height: f64) -> (f64, f64) {
+        let m = &self.m;
+        (
+            m[0][0].abs() * width + m[0][1].abs() * height,
+            m[1][0].abs() * width + m[1][1].abs() * height,
+        )
+    }
+
+    pub fn mm_per_px(&self) -> f64 {
+        let m = &self.m;
+        (m[0][0] * m[1][1] - m[0][1] * m[1][0]).abs().sqrt()
+    }
+
+    /// Angle of the image x axis from the gantry x axis.
+    pub fn rotation_deg(&self) -> f64 {
+        self.m[1][0].atan2(self.m[0][0]).to_degrees()
+    }
+}
+
+/// Centre of the fiducial as a pixel offset from the image centre: the
+/// largest dark blob not cut by the image border.
+pub fn locate_fiducial(image: &DynamicImage) -> anyhow::Result<(f64, f64)> {
+    let gray = image.to_luma8();
+    let dark = threshold(&gray, otsu_level(&gray), ThresholdType::BinaryInverted);
+    let labels = connected_components(&dark, Connectivity::Eight, Luma([0u8]));
+    let (width, height) = labels.dimensions();
+
+    // Pixel count, coordinate sums and whether the blob reaches the border.
+    let mut blobs: HashMap<u32, (u64, f64, f64, bool)> = HashMap::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label[0] == 0 {
+            continue;
+        }
+        let blob = blobs.entry(label[0]).or_default();
+        blob.0 += 1;
+        blob.1 += x as f64;
+        blob.2 += y as f64;
+        blob.3 |= x == 0 || y == 0 || x == width - 1 || y == height - 1;
+    }
+    let Some((count, sum_x, sum_y, _)) = blobs
+        .into_values()
+        .filter(|blob| !blob.3 && blob.0 >= 50)
+        .max_by_key(|blob| blob.0)
+    else {
+        bail!("no fiducial found in the image");
+    };
+    Ok((
+        sum_x / count as f64 - width as f64 / 2.0,
+        sum_y / count as f64 - height as f64 / 2.0,
+    ))
+}
+
+/// Fiducial seen from one carriage position.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub carriage: (f64, f64),
+    pub pixel: (f64, f64),
+}
+
+#[derive(Getters, Debug, Clone)]
+pub struct Calibration {
+    pub transform: PixelTransform,
+    /// Bed position of the image centre relative to the carriage.
+    pub offset: (f64, f64),
+    pub capture_z: Option<u32>,
+    /// RMS distance between the observed and the fitted fiducial motion.
+    pub residual_mm: f64,
+}
+
+impl Calibration {
+    /// Least-squares fit over the samples. The fiducial stays put, so the
+    /// carriage moving by `d` must move it by `-d` in the image.
+    pub fn solve(
+        samples: &[Sample],
+        fiducial: (f64, f64),
+        capture_z: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        let Some(first) = samples.first() else {
+            bail!("no calibration samples");
+        };
+        let moves: Vec<_> = samples[1..]
+            .iter()
+            .map(|s| {
+                let dp = (s.pixel.0 - first.pixel.0, s.pixel.1 - first.pixel.1);
+                let dg = (
+                    s.carriage.0 - first.carriage.0,
+                    s.carriage.1 - first.carriage.1,
+                );
+                (dp, dg)
+            })
+            .collect();
+
+        // M = G Pᵀ (P Pᵀ)⁻¹ with P the pixel moves and G the bed moves.
+        let (mut pp, mut gp) = ([[0.0; 2]; 2], [[0.0; 2]; 2]);
+        for ((px, py), (gx, gy)) in &moves {
+            let (p, g) = ([*px, *py], [-gx, -gy]);
+            for i in 0..2 {
+                for j in 0..2 {
+                    pp[i][j] += p[i] * p[j];
+                    gp[i][j] += g[i] * p[j];
+                }
+            }
+        }
+        let det = pp[0][0] * pp[1][1] - pp[0][1] * pp[1][0];
+        if det.abs() < 1e-6 {
+            bail!("the fiducial did not move across the image, check step_mm");
+        }
+        let inv = [
+            [pp[1][1] / det, -pp[0][1] / det],
+            [-pp[1][0] / det, pp[0][0] / det],
+        ];
+        let mut m = [[0.0; 2]; 2];
+        for i in 0..2 {
+            for j in 0..2 {
+                m[i][j] = gp[i][0] * inv[0][j] + gp[i][1] * inv[1][j];
+            }
+        }
+        let transform = PixelTransform { m };
+
+        let residual_mm = (moves
+            .iter()
+            .map(|((px, py), (gx, gy))| {
+                let (mx, my) = transform.to_mm(*px, *py);
+                (mx + gx).powi(2) + (my + gy).powi(2)
+            })
+            .sum::<f64>()
+            / moves.len() as f64)
+            .sqrt();
+
+        let n = samples.len() as f64;
+        let offset = samples.iter().fold((0.0, 0.0), |acc, s| {
+            let (mx, my) = transform.to_mm(s.pixel.0, s.pixel.1);
+            (
+                acc.0 + (fiducial.0 - s.carriage.0 - mx) / n,
+                acc.1 + (fiducial.1 - s.carriage.1 - my) / n,
+            )
+        });
+
+        Ok(Self {
+            transform,
+            offset,
+            capture_z,
+            residual_mm,
+        })
+    }
+
+    pub fn to_data(&self, created_ts: i64) -> CameraCalibrationData {
+        let m = &self.transform.m;
+        CameraCalibrationData {
+            m11: m[0][0],
+            m12: m[0][1],
+            m21: m[1][0],
+            m22: m[1][1],
+            offset_x: self.offset.0,
+            offset_y: self.offset.1,
+            capture_z: self.capture_z.map(|z| z as i64),
+            residual_mm: self.residual_mm,
+            created_ts,
+        }
+    }
+}
+
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.4} mm/px, rotated {:.1}°, camera offset ({:.1}, {:.1}) mm, residual {:.2} mm",
+            self.transform.mm_per_px(),
+            self.transform.rotation_deg(),
+            self.offset.0,
+            self.offset.1,
+            self.residual_mm
+        )
+    }
+}
+
+impl From<CameraCalibrationData> for Calibration {
+    fn from(data: CameraCalibrationData) -> Self {
+        Self {
+            transform: PixelTransform {
+                m: [[data.m11, data.m12], [data.m21, data.m22]],
+            },
+            offset: (data.offset_x, data.offset_y),
+            capture_z: data.capture_z.map(|z| z as u32),
+            residual_mm: data.residual_mm,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// Fiducial pixel offsets seen from each carriage position with the
+    /// camera described by `m` and `offset`.
+    fn samples(m: [[f64; 2]; 2], offset: (f64, f64), fiducial: (f64, f64)) -> Vec<Sample> {
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let config = CalibrationConfig {
+            fiducial_x: fiducial.0 as u32,
+            fiducial_y: fiducial.1 as u32,
+            step_mm: 20,
+            capture_z: None,
+        };
+        config
+            .targets()
+            .into_iter()
+            .map(|(x, y)| {
+                let (gx, gy) = (
+                    fiducial.0 - x as f64 - offset.0,
+                    fiducial.1 - y as f64 - offset.1,
+                );
+                Sample {
+                    carriage: (x as f64, y as f64),
+                    pixel: (
+                        (m[1][1] * gx - m[0][1] * gy) / det,
+                        (m[0][0] * gy - m[1][0] * gx) / det,
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solve_recovers_scale_rotation_and_mirroring() {
+        let (scale, angle) = (0.12, 30f64.to_radians());
+        // The image y axis points against the gantry y axis.
+        let m = [
+            [scale * angle.cos(), scale * angle.sin()],
+            [scale * angle.sin(), -scale * angle.cos()],
+        ];
+        let fiducial = (100.0, 80.0);
+        let calibration =
+            Calibration::solve(&samples(m, (5.0, -3.0), fiducial), fiducial, Some(40)).unwrap();
+
+        let fitted = calibration.transform.m.iter().flatten();
+        for (fitted, expected) in fitted.zip(m.iter().flatten()) {
+            assert!(
+                (fitted - expected).abs() < 1e-9,
+                "{:?}",
+                calibration.transform
+            );
+        }
+        assert!((calibration.offset.0 - 5.0).abs() < 1e-6);
+        assert!((calibration.offset.1 + 3.0).abs() < 1e-6);
+        assert!(calibration.residual_mm < 1e-6);
+        assert!((calibration.transform.mm_per_px() - scale).abs() < 1e-9);
+        assert_eq!(calibration.capture_z, Some(40));
+    }
+
+    #[test]
+    fn solve_rejects_a_fiducial_that_did_not_move() {
+        let still = Sample {
+            carriage: (0.0, 0.0),
+            pixel: (10.0, 10.0),
+        };
+        let err = Calibration::solve(&[still; 5], (0.0, 0.0), None).unwrap_err();
+        assert!(err.to_string().contains("did not move"), "{err}");
+    }
+
+    #[test]
+    fn fiducial_is_the_centroid_of_the_dark_square() {
+        let mut image = GrayImage::from_pixel(200, 100, Luma([230]));
+        for x in 120..140 {
+            for y in 20..40 {
+                image.put_pixel(x, y, Luma([20]));
+            }
+        }
+        // Cut by the border, so not the fiducial however large.
+        for x in 0..60 {
+            for y in 0..100 {
+                image.put_pixel(x, y, Luma([20]));
+            }
+        }
+        let (x, y) = locate_fiducial(&DynamicImage::ImageLuma8(image)).unwrap();
+        assert!(
+            (x - 29.5).abs() < 1e-9 && (y + 20.5).abs() < 1e-9,
+            "{x}, {y}"
+        );
+    }
+
+    #[test]
+    fn blank_image_has_no_fiducial() {
+        let image = GrayImage::from_pixel(64, 64, Luma([230]));
+        assert!(locate_fiducial(&DynamicImage::ImageLuma8(image)).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_std::task::sleep;
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::actuator::estop;
+use super::actuator::gpio_pin::LocalGpioConfig;
+use super::actuator::watchdog;
+use crate::database;
+
+/// Time of day as minutes since midnight, written `"HH:MM"` in the
+/// configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClockTime(u32);
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = || {
+            let (hours, minutes) = value.split_once(':')?;
+            let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(ClockTime(hours * 60 + minutes))
+        };
+        parse().ok_or_else(|| format!("invalid time of day `{value}`, expected HH:MM"))
+    }
+}
+
+impl From<ClockTime> for String {
+    fn from(time: ClockTime) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
+impl ClockTime {
+    pub fn now() -> Self {
+        // SAFETY: localtime_r only writes to the `tm` it is given.
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        ClockTime((tm.tm_hour * 60 + tm.tm_min) as u32)
+    }
+}
+
+/// Part of the day a controller runs in. `from` after `to` wraps past
+/// midnight.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct TimeWindow {
+    pub from: ClockTime,
+    pub to: ClockTime,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: ClockTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+/// Which way switching the relay on pushes the measured quantity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Humidifiers and heaters: on below the setpoint.
+    #[default]
+    Raise,
+    /// Fans and coolers: on above the setpoint.
+    Lower,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    /// On/off around the setpoint, `band` wide.
+    Hysteresis { band: f64 },
+    /// Time-proportioned output: the relay is on for a share of every
+    /// period.
+    Pid { kp: f64, ki: f64, kd: f64 },
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control::Hysteresis { band: 1.0 }
+    }
+}
+
+/// Sensor quantity a controller regulates.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct Feedback {
+    pub sensor: String,
+    pub quantity: String,
+}
+
+/// Relay driving a fan, humidifier, heater or light.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct ClimateConfig {
+    pub pin: LocalGpioConfig,
+    #[serde(default)]
+    pub active_low: bool,
+    /// Without feedback the relay is simply on during the schedule, as for
+    /// grow lights.
+    #[serde(default)]
+    pub feedback: Option<Feedback>,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub control: Control,
+    #[serde(default)]
+    pub setpoint: f64,
+    /// Setpoints overriding `setpoint` while most positions are in a stage.
+    #[serde(default)]
+    pub stage_setpoints: BTreeMap<String, f64>,
+    /// Windows the controller runs in; always when empty.
+    #[serde(default)]
+    pub schedule: Vec<TimeWindow>,
+    #[serde(default = "default_period_s")]
+    pub period_s: u64,
+    /// Readings older than this switch the relay off.
+    #[serde(default = "default_max_age_s")]
+    pub max_age_s: u64,
+}
+
+fn default_period_s() -> u64 {
+    30
+}
+
+fn default_max_age_s() -> u64 {
+    300
+}
+
+/// What a controller decided for the coming period.
+struct Decision {
+    /// Share of the period the relay is on.
+    duty: f64,
+    value: Option<f64>,
+    setpoint: Option<f64>,
+}
+
+impl Decision {
+    fn off() -> Self {
+        Self {
+            duty: 0.0,
+            value: None,
+            setpoint: None,
+        }
+    }
+}
+
+struct Controller {
+    name: String,
+    config: ClimateConfig,
+    active: Option<bool>,
+    integral: f64,
+    last_error: Option<f64>,
+}
+
+impl Controller {
+    async fn decide(&mut self) -> anyhow::Result<Decision> {
+        let config = &self.config;
+        let now = ClockTime::now();
+        if !config.schedule.is_empty() && !config.schedule.iter().any(|w| w.contains(now)) {
+            self.last_error = None;
+            return Ok(Decision::off());
+        }
+        let Some(feedback) = &config.feedback else {
+            return Ok(Decision {
+                duty: 1.0,
+                value: None,
+                setpoint: None,
+            });
+        };
+
+        let setpoint = match database::query_dominant_stage().await? {
+            Some(stage) => config
+                .stage_setpoints
+                .get(&stage)
+                .copied()
+                .unwrap_or(config.setpoint),
+            None => config.setpoint,
+        };
+        let reading =
+            database::query_latest_sensor_reading(&feedback.sensor, &feedback.quantity).await?;
+        let Some(reading) = reading
+            .filter(|reading| reading.created_ts + config.max_age_s as i64 >= super::timestamp())
+        else {
+            log::warn!(
+                "Climate {}: no recent {} from {}, switching off",
+                self.name,
+                feedback.quantity,
+                feedback.sensor
+            );
+            return Ok(Decision::off());
+        };
+
+        let value = reading.value;
+        let error = match config.direction {
+            Direction::Raise => setpoint - value,
+            Direction::Lower => value - setpoint,
+        };
+        let duty = match config.control {
+            Control::Hysteresis { band } => {
+                if error > band / 2.0 {
+                    1.0
+                } else if error < -band / 2.0 {
+                    0.0
+                } else if self.active == Some(true) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Control::Pid { kp, ki, kd } => {
+                let dt = config.period_s.max(1) as f64;
+                self.integral += error * dt;
+                // Keep the integral term within the output range so it
+                // cannot wind up while the relay is saturated.
+                if ki != 0.0 {
+                    self.integral = self.integral.clamp(0.0, 1.0 / ki.abs());
+                }
+                let derivative = self.last_error.map_or(0.0, |last| (error - last) / dt);
+                self.last_error = Some(error);
+                (kp * error + ki * self.integral + kd * derivative).clamp(0.0, 1.0)
+            }
+        };
+        Ok(Decision {
+            duty,
+            value: Some(value),
+            setpoint: Some(setpoint),
+        })
+    }
+
+    /// Switches the relay, recording every change.
+    async fn set(&mut self, active: bool, decision: &Decision) {
+        if self.active == Some(active) {
+            return;
+        }
+        let res = if active ^ self.config.active_low {
+            self.config.pin.set_high()
+        } else {
+            self.config.pin.set_low()
+        };
+        if let Err(e) = res {
+            log::error!("Climate {}: unable to switch relay: {e}", self.name);
+            return;
+        }
+        self.active = Some(active);
+        log::info!(
+            "Climate {} {} (value {:?}, setpoint {:?})",
+            self.name,
+            if active { "on" } else { "off" },
+            decision.value,
+            decision.setpoint
+        );
+        let res = database::insert_climate_actuation(database::ClimateActuationData {
+            controller: self.name.clone(),
+            active,
+            value: decision.value,
+            setpoint: decision.setpoint,
+            created_ts: super::timestamp(),
+        })
+        .await;
+        if let Err(e) = res {
+            log::error!("Climate {}: unable to record actuation: {e}", self.name);
+        }
+    }
+
+    async fn run(mut self) {
+        let period = Duration::from_secs(self.config.period_s.max(1));
+        loop {
+            if estop::is_halted() {
+                self.set(false, &Decision::off()).await;
+                sleep(period).await;
+                continue;
+            }
+            let decision = self.decide().await.unwrap_or_else(|e| {
+                log::error!("Climate {}: {e}, switching off", self.name);
+                Decision::off()
+            });
+            let on_for = period.mul_f64(decision.duty);
+            if !on_for.is_zero() {
+                self.set(true, &decision).await;
+                sleep(on_for).await;
+            }
+            if on_for < period {
+                self.set(false, &decision).await;
+                sleep(period - on_for).await;
+            }
+        }
+    }
+}
+
+/// Relays with the level that switches them off, for the emergency stop.
+pub fn safe_outputs(climate: &BTreeMap<String, ClimateConfig>) -> Vec<(LocalGpioConfig, bool)> {
+    climate
+        .values()
+        .map(|config| (config.pin.clone(), config.active_low))
+        .collect()
+}
+
+/// Runs every controller on its own task, with its relay switched off on
+/// shutdown and on panic.
+pub fn start(climate: BTreeMap<String, ClimateConfig>) {
+    for (name, config) in climate {
+        if let Err(e) = watchdog::guard_output(&config.pin, config.active_low) {
+            log::error!("Climate {name}: relay is not guarded by the watchdog: {e}");
+        }
+        async_std::task::spawn(
+            Controller {
+                name,
+                config,
+                active: None,
+                integral: 0.0,
+                last_error: None,
+            }
+            .run(),
+        );
+    }
+}
@@ -22,7 +22,7 @@ pub struct DetectionResult {
 }
 
 impl DetectionResult {
-    fn center(&self) -> (u32, u32) {
+    pub fn center(&self) -> (u32, u32) {
         (self.x + self.width / 2, self.y + self.height / 2)
     }
     fn distance_from_point(&self, point: (u32, u32)) -> u32 {
@@ -39,11 +39,20 @@ impl Default for DetectorConfig {
 }
 
 impl DetectorConfig {
+    /// Every detection in the image.
+    pub async fn detect_all(
+        &mut self,
+        img: &image::DynamicImage,
+    ) -> anyhow::Result<Vec<DetectionResult>> {
+        match self {
+            DetectorConfig::YoloV8(config) => config.get_bounding_boxes(img).await,
+            DetectorConfig::Robo(config) => config.detect(img).await,
+        }
+    }
+
+    /// The detection nearest the image centre.
     pub async fn detect(&mut self, img: &image::DynamicImage) -> anyhow::Result<DetectionResult> {
-        let detections = match self {
-            DetectorConfig::YoloV8(config) => config.get_bounding_boxes(img).await?,
-            DetectorConfig::Robo(config) => config.detect(img).await?,
-        };
+        let detections = self.detect_all(img).await?;
 
         let cx = img.width() / 2;
         let cy = img.height() / 2;
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, bail};
+
+/// Largest grid generated at once, guarding against a mistyped pitch.
+const MAX_POSITIONS: usize = 1000;
+
+/// How a grid of positions is laid out, in bed mm.
+#[derive(Debug, Clone)]
+pub enum GridSpec {
+    /// `rows` by `columns` positions, the first at `(x, y)`.
+    Pitch {
+        x: u32,
+        y: u32,
+        rows: u32,
+        columns: u32,
+        pitch_x: u32,
+        pitch_y: u32,
+    },
+    /// Fewest camera positions whose frames, overlapping by `overlap_pct`,
+    /// cover the rectangle from `(x, y)`.
+    Coverage {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        overlap_pct: f64,
+    },
+}
+
+/// Number of frames `fov` wide and at most `step` apart needed to span
+/// `length`.
+fn frames(length: u32, fov: f64, step: f64) -> f64 {
+    let length = length as f64;
+    if length <= fov {
+        1.0
+    } else {
+        ((length - fov) / step).ceil() + 1.0
+    }
+}
+
+/// Centres of `count` frames `fov` wide evenly spread to span `length` from
+/// `start`.
+fn cover(start: u32, length: u32, fov: f64, count: u32) -> Vec<u32> {
+    let length = length as f64;
+    if count <= 1 {
+        return vec![(start as f64 + length / 2.0).round() as u32];
+    }
+    let first = start as f64 + fov / 2.0;
+    let spacing = (length - fov) / (count - 1) as f64;
+    (0..count)
+        .map(|i| (first + spacing * i as f64).round() as u32)
+        .collect()
+}
+
+/// `count` coordinates `pitch` apart from `start`.
+fn steps(start: u32, count: u32, pitch: u32) -> anyhow::Result<Vec<u32>> {
+    (0..count)
+        .map(|i| {
+            i.checked_mul(pitch)
+                .and_then(|offset| offset.checked_add(start))
+                .ok_or_else(|| anyhow!("the grid extends past the largest coordinate"))
+        })
+        .collect()
+}
+
+fn check_count(count: f64) -> anyhow::Result<()> {
+    if count > MAX_POSITIONS as f64 {
+        bail!("{count} positions requested, at most {MAX_POSITIONS} at once");
+    }
+    Ok(())
+}
+
+impl GridSpec {
+    /// Positions row by row, alternating direction so the gantry snakes
+    /// through them. `fov` is the bed area a frame covers, needed for
+    /// coverage grids.
+    pub fn plan(&self, fov: Option<(f64, f64)>) -> anyhow::Result<Vec<(u32, u32)>> {
+        let (xs, ys) = match *self {
+            GridSpec::Pitch {
+                x,
+                y,
+                rows,
+                columns,
+                pitch_x,
+                pitch_y,
+            } => {
+                check_count(rows as f64 * columns as f64)?;
+                if (pitch_x == 0 && columns > 1) || (pitch_y == 0 && rows > 1) {
+                    bail!("a zero pitch puts several positions at the same place");
+                }
+                (steps(x, columns, pitch_x)?, steps(y, rows, pitch_y)?)
+            }
+            GridSpec::Coverage {
+                x,
+                y,
+                width,
+                height,
+                overlap_pct,
+            } => {
+                let Some((fov_x, fov_y)) = fov else {
+                    bail!("the camera has no pixel to mm transform, calibrate it first");
+                };
+                if !(0.0..100.0).contains(&overlap_pct) {
+                    bail!("overlap must be at least 0 % and below 100 %");
+                }
+                if x.checked_add(width).is_none() || y.checked_add(height).is_none() {
+                    bail!("the area extends past the largest coordinate");
+                }
+                let keep = 1.0 - overlap_pct / 100.0;
+                let (step_x, step_y) = (fov_x * keep, fov_y * keep);
+                if step_x < 1.0 || step_y < 1.0 {
+                    bail!("{overlap_pct} % overlap leaves frames less than 1 mm apart");
+                }
+                let (columns, rows) = (frames(width, fov_x, step_x), frames(height, fov_y, step_y));
+                check_count(columns * rows)?;
+                (
+                    cover(x, width, fov_x, columns as u32),
+                    cover(y, height, fov_y, rows as u32),
+                )
+            }
+        };
+
+        let mut positions = Vec::with_capacity(xs.len() * ys.len());
+        for (row, &y) in ys.iter().enumerate() {
+            if row % 2 == 0 {
+                positions.extend(xs.iter().map(|&x| (x, y)));
+            } else {
+                positions.extend(xs.iter().rev().map(|&x| (x, y)));
+            }
+        }
+        Ok(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitch(x: u32, rows: u32, columns: u32, pitch_x: u32) -> GridSpec {
+        GridSpec::Pitch {
+            x,
+            y: 0,
+            rows,
+            columns,
+            pitch_x,
+            pitch_y: 10,
+        }
+    }
+
+    fn coverage(width: u32, overlap_pct: f64) -> GridSpec {
+        GridSpec::Coverage {
+            x: 0,
+            y: 0,
+            width,
+            height: 100,
+            overlap_pct,
+        }
+    }
+
+    #[test]
+    fn pitch_grid_snakes_through_rows() {
+        let positions = pitch(5, 2, 3, 10).plan(None).unwrap();
+        assert_eq!(
+            positions,
+            vec![(5, 0), (15, 0), (25, 0), (25, 10), (15, 10), (5, 10)]
+        );
+    }
+
+    #[test]
+    fn pitch_grid_rejects_overflow_and_huge_counts() {
+        assert!(pitch(u32::MAX - 5, 1, 2, 10).plan(None).is_err());
+        assert!(pitch(0, 1, 3, u32::MAX / 2 + 1).plan(None).is_err());
+        assert!(pitch(0, u32::MAX, u32::MAX, 1).plan(None).is_err());
+        assert!(pitch(0, 1, 3, 0).plan(None).is_err());
+        assert_eq!(pitch(5, 1, 1, 0).plan(None).unwrap(), vec![(5, 0)]);
+    }
+
+    #[test]
+    fn coverage_grid_spans_the_area() {
+        let positions = coverage(100, 0.0).plan(Some((40.0, 100.0))).unwrap();
+        assert_eq!(positions, vec![(20, 50), (50, 50), (80, 50)]);
+    }
+
+    #[test]
+    fn coverage_grid_rejects_extreme_overlap() {
+        let fov = Some((40.0, 40.0));
+        assert!(coverage(100, 99.99).plan(fov).is_err());
+        assert!(coverage(u32::MAX, 0.0).plan(fov).is_err());
+        assert!(coverage(100, 100.0).plan(fov).is_err());
+        assert!(coverage(100, 50.0).plan(None).is_err());
+    }
+}
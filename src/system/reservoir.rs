@@ -0,0 +1,252 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use derive_getters::Getters;
+use serde::{Deserialize, Serialize};
+
+use super::actuator::gpio_pin::{self, LocalGpioConfig};
+use crate::database;
+
+/// Set while watering is locked out, so the alert is raised once.
+static LOW: AtomicBool = AtomicBool::new(false);
+/// Last level read by the ultrasonic sensor, with when it was read.
+static MEASURED: Mutex<Option<(f64, Instant)>> = Mutex::new(None);
+
+/// HC-SR04 style ultrasonic sensor looking down at the water surface.
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct UltrasonicConfig {
+    pub trigger: LocalGpioConfig,
+    pub echo: LocalGpioConfig,
+    /// Distance to the surface of an empty reservoir.
+    pub empty_mm: f64,
+    /// Distance to the surface of a full one.
+    pub full_mm: f64,
+    #[serde(default = "default_period_s")]
+    pub period_s: u64,
+    /// Change in volume worth storing a new level for.
+    #[serde(default = "default_record_change_ml")]
+    pub record_change_ml: f64,
+    /// The level is stored at least this often, even when steady.
+    #[serde(default = "default_record_period_s")]
+    pub record_period_s: u64,
+}
+
+fn default_period_s() -> u64 {
+    30
+}
+
+fn default_record_change_ml() -> f64 {
+    50.0
+}
+
+fn default_record_period_s() -> u64 {
+    3600
+}
+
+impl Default for UltrasonicConfig {
+    fn default() -> Self {
+        Self {
+            trigger: Default::default(),
+            echo: Default::default(),
+            empty_mm: 0.0,
+            full_mm: 0.0,
+            period_s: default_period_s(),
+            record_change_ml: default_record_change_ml(),
+            record_period_s: default_record_period_s(),
+        }
+    }
+}
+
+impl UltrasonicConfig {
+    fn wait_for(&self, echo: &mut LocalGpioConfig, high: bool) -> anyhow::Result<Instant> {
+        let deadline = Instant::now() + Duration::from_millis(30);
+        while echo.is_high()? != high {
+            if Instant::now() > deadline {
+                bail!("no echo from the ultrasonic sensor");
+            }
+        }
+        Ok(Instant::now())
+    }
+
+    fn distance_mm(&self) -> anyhow::Result<f64> {
+        if self.echo.chip == Path::new("stub") {
+            bail!("ultrasonic sensor on a stub line");
+        }
+        let (mut trigger, mut echo) = (self.trigger.clone(), self.echo.clone());
+        let mut samples = Vec::with_capacity(5);
+        for _ in 0..5 {
+            trigger.set_high()?;
+            thread::sleep(Duration::from_micros(10));
+            trigger.set_low()?;
+            let start = self.wait_for(&mut echo, true)?;
+            let end = self.wait_for(&mut echo, false)?;
+            // Sound travels there and back at about 343 m/s.
+            samples.push((end - start).as_secs_f64() * 343_000.0 / 2.0);
+            thread::sleep(Duration::from_millis(60));
+        }
+        samples.sort_by(f64::total_cmp);
+        Ok(samples[samples.len() / 2])
+    }
+
+    /// Volume in the reservoir, assuming straight walls.
+    fn level_ml(&self, capacity_ml: f64) -> anyhow::Result<f64> {
+        let distance = self.distance_mm()?;
+        let depth = self.empty_mm - self.full_mm;
+        if depth <= 0.0 {
+            bail!("empty_mm must be greater than full_mm");
+        }
+        Ok((capacity_ml * (self.empty_mm - distance) / depth).clamp(0.0, capacity_ml))
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReservoirConfig {
+    pub capacity_ml: f64,
+    /// Watering is locked out below this volume.
+    pub low_ml: f64,
+    /// Named input of a float switch, active once the water is below it.
+    #[serde(default)]
+    pub float_input: Option<String>,
+    #[serde(default)]
+    pub ultrasonic: Option<UltrasonicConfig>,
+}
+
+#[derive(Getters, Debug, Clone)]
+pub struct ReservoirStatus {
+    pub level_ml: f64,
+    pub capacity_ml: f64,
+    /// Why watering is locked out, `None` while it is allowed.
+    pub lockout: Option<String>,
+}
+
+impl ReservoirConfig {
+    /// Measures the level on its own thread every `period_s`, storing it
+    /// when it changed or at the latest every `record_period_s`.
+    pub fn start_polling(&self) {
+        let Some(ultrasonic) = self.ultrasonic.clone() else {
+            return;
+        };
+        let capacity_ml = self.capacity_ml;
+        let res = thread::Builder::new()
+            .name("reservoir-level".to_owned())
+            .spawn(move || poll(ultrasonic, capacity_ml));
+        if let Err(e) = res {
+            log::error!("Unable to spawn reservoir level thread: {e}");
+        }
+    }
+
+    /// Latest volume read by the ultrasonic sensor, unless it stopped
+    /// answering.
+    fn measured_ml(&self) -> Option<f64> {
+        let ultrasonic = self.ultrasonic.as_ref()?;
+        let max_age = Duration::from_secs(ultrasonic.period_s.max(1) * 3);
+        MEASURED
+            .lock()
+            .unwrap()
+            .filter(|(_, at)| at.elapsed() <= max_age)
+            .map(|(level_ml, _)| level_ml)
+    }
+
+    /// Capacity at the last refill minus what was dispensed since, or the
+    /// last measurement. A reservoir never refilled is assumed full.
+    async fn estimated_ml(&self) -> anyhow::Result<f64> {
+        Ok(database::query_reservoir_level()
+            .await?
+            .map_or(self.capacity_ml, |level| level.level_ml))
+    }
+
+    async fn level_ml(&self) -> anyhow::Result<f64> {
+        match self.measured_ml() {
+            Some(level_ml) => Ok(level_ml),
+            None => self.estimated_ml().await,
+        }
+    }
+
+    pub async fn status(&self) -> anyhow::Result<ReservoirStatus> {
+        let level_ml = self.level_ml().await?;
+        let float_low = match &self.float_input {
+            Some(input) => gpio_pin::is_active(input)?,
+            None => false,
+        };
+        let lockout = if float_low {
+            Some("float switch reports a low level".to_owned())
+        } else if level_ml < self.low_ml {
+            Some(format!(
+                "{level_ml:.0} ml left, below the {:.0} ml threshold",
+                self.low_ml
+            ))
+        } else {
+            None
+        };
+
+        match &lockout {
+            Some(reason) if !LOW.swap(true, Ordering::SeqCst) => {
+                log::error!("Reservoir empty, watering locked out: {reason}");
+            }
+            None if LOW.swap(false, Ordering::SeqCst) => {
+                log::info!("Reservoir refilled, watering resumed");
+            }
+            _ => (),
+        }
+        Ok(ReservoirStatus {
+            level_ml,
+            capacity_ml: self.capacity_ml,
+            lockout,
+        })
+    }
+
+    pub async fn dispensed(&self, ml: f64) -> anyhow::Result<()> {
+        if self.measured_ml().is_some() {
+            return Ok(());
+        }
+        let level_ml = self.estimated_ml().await?;
+        record((level_ml - ml).max(0.0), "dispensed").await
+    }
+
+    pub async fn refill(&self) -> anyhow::Result<()> {
+        record(self.capacity_ml, "refill").await
+    }
+}
+
+async fn record(level_ml: f64, source: &str) -> anyhow::Result<()> {
+    database::insert_reservoir_level(database::ReservoirLevelData {
+        level_ml,
+        source: source.to_owned(),
+        created_ts: super::timestamp(),
+    })
+    .await?;
+    Ok(())
+}
+
+fn poll(ultrasonic: UltrasonicConfig, capacity_ml: f64) {
+    let mut recorded: Option<(f64, Instant)> = None;
+    let mut failing = false;
+    loop {
+        match ultrasonic.level_ml(capacity_ml) {
+            Ok(level_ml) => {
+                failing = false;
+                MEASURED.lock().unwrap().replace((level_ml, Instant::now()));
+                let due = recorded.is_none_or(|(last_ml, at)| {
+                    (level_ml - last_ml).abs() >= ultrasonic.record_change_ml
+                        || at.elapsed() >= Duration::from_secs(ultrasonic.record_period_s)
+                });
+                if due {
+                    match async_std::task::block_on(record(level_ml, "ultrasonic")) {
+                        Ok(()) => recorded = Some((level_ml, Instant::now())),
+                        Err(e) => log::error!("Unable to store the reservoir level: {e}"),
+                    }
+                }
+            }
+            Err(e) if !failing => {
+                failing = true;
+                log::warn!("Reservoir level not measured, using the estimate: {e}");
+            }
+            Err(_) => (),
+        }
+        thread::sleep(Duration::from_secs(ultrasonic.period_s.max(1)));
+    }
+}
@@ -0,0 +1,396 @@
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use derive_getters::Getters;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+use linux_embedded_hal::{Delay, I2cdev};
+use serde::{Deserialize, Serialize};
+
+use crate::database;
+
+mod scd30;
+mod scd4x;
+mod sht3x;
+
+/// One sample of whatever the part measures.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reading {
+    pub temperature_c: Option<f64>,
+    pub humidity_pct: Option<f64>,
+    pub co2_ppm: Option<f64>,
+}
+
+impl Reading {
+    /// Measured quantities, named as stored in the database.
+    pub fn quantities(&self) -> Vec<(&'static str, f64)> {
+        [
+            ("temperature_c", self.temperature_c),
+            ("humidity_pct", self.humidity_pct),
+            ("co2_ppm", self.co2_ppm),
+        ]
+        .into_iter()
+        .filter_map(|(quantity, value)| value.map(|value| (quantity, value)))
+        .collect()
+    }
+}
+
+pub trait Sensor {
+    /// Takes a sample, `None` when the part has no new one yet.
+    fn read(&mut self) -> anyhow::Result<Option<Reading>>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorKind {
+    Sht3x,
+    Scd30,
+    Scd4x,
+}
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone)]
+pub struct SensorConfig {
+    pub name: String,
+    pub kind: SensorKind,
+    #[serde(default = "default_bus")]
+    pub bus: PathBuf,
+    /// 7-bit address, the part's default when unset.
+    #[serde(default)]
+    pub address: Option<u8>,
+    #[serde(default = "default_period_s")]
+    pub period_s: u64,
+}
+
+fn default_bus() -> PathBuf {
+    "/dev/i2c-1".into()
+}
+
+fn default_period_s() -> u64 {
+    60
+}
+
+impl SensorConfig {
+    /// Driver for the part on `i2c`, which may be a mock bus.
+    pub fn driver<I, D>(&self, i2c: I, delay: D) -> Box<dyn Sensor + Send>
+    where
+        I: I2c + Send + 'static,
+        D: DelayNs + Send + 'static,
+    {
+        match self.kind {
+            SensorKind::Sht3x => Box::new(sht3x::Sht3x::new(
+                i2c,
+                delay,
+                self.address.unwrap_or(sht3x::ADDRESS),
+            )),
+            SensorKind::Scd30 => Box::new(scd30::Scd30::new(
+                i2c,
+                delay,
+                self.address.unwrap_or(scd30::ADDRESS),
+            )),
+            SensorKind::Scd4x => Box::new(scd4x::Scd4x::new(
+                i2c,
+                delay,
+                self.address.unwrap_or(scd4x::ADDRESS),
+            )),
+        }
+    }
+
+    fn open(&self) -> anyhow::Result<Box<dyn Sensor + Send>> {
+        Ok(self.driver(I2cdev::new(&self.bus)?, Delay))
+    }
+}
+
+/// Polls every sensor on its own thread and stores each quantity as a time
+/// series.
+pub fn start_polling(sensors: Vec<SensorConfig>) {
+    for config in sensors {
+        let res = thread::Builder::new()
+            .name(format!("sensor-{}", config.name))
+            .spawn(move || poll(config));
+        if let Err(e) = res {
+            log::error!("Unable to spawn sensor thread: {e}");
+        }
+    }
+}
+
+fn poll(config: SensorConfig) {
+    let mut sensor = match config.open() {
+        Ok(sensor) => sensor,
+        Err(e) => {
+            log::error!("Sensor {} unavailable: {e}", config.name);
+            return;
+        }
+    };
+    loop {
+        match sensor.read() {
+            Ok(Some(reading)) => {
+                let created_ts = super::timestamp();
+                for (quantity, value) in reading.quantities() {
+                    let res = async_std::task::block_on(database::insert_sensor_reading(
+                        database::SensorReadingData {
+                            sensor: config.name.clone(),
+                            quantity: quantity.to_owned(),
+                            value,
+                            created_ts,
+                        },
+                    ));
+                    if let Err(e) = res {
+                        log::error!("Unable to store reading of {}: {e}", config.name);
+                    }
+                }
+            }
+            Ok(None) => log::debug!("Sensor {} has no new sample", config.name),
+            Err(e) => log::warn!("Sensor {} read failed: {e}", config.name),
+        }
+        thread::sleep(Duration::from_secs(config.period_s.max(1)));
+    }
+}
+
+fn i2c_error<E: embedded_hal::i2c::Error>(e: E) -> anyhow::Error {
+    anyhow!("I2C transfer failed: {:?}", e.kind())
+}
+
+/// Sensirion CRC-8 (polynomial 0x31, initial value 0xff) protecting every
+/// 16-bit word on the bus.
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// A 16-bit command followed by its argument words, each with its CRC.
+fn command(cmd: u16, args: &[u16]) -> Vec<u8> {
+    let mut bytes = cmd.to_be_bytes().to_vec();
+    for arg in args {
+        let word = arg.to_be_bytes();
+        bytes.extend(word);
+        bytes.push(crc8(&word));
+    }
+    bytes
+}
+
+/// Sends `cmd`, waits `wait_ms` for the part to prepare its answer and reads
+/// `count` words back.
+fn query<I: I2c, D: DelayNs>(
+    i2c: &mut I,
+    delay: &mut D,
+    address: u8,
+    cmd: u16,
+    wait_ms: u32,
+    count: usize,
+) -> anyhow::Result<Vec<u16>> {
+    i2c.write(address, &command(cmd, &[])).map_err(i2c_error)?;
+    delay.delay_ms(wait_ms);
+    let mut data = vec![0u8; count * 3];
+    i2c.read(address, &mut data).map_err(i2c_error)?;
+    data.chunks(3)
+        .map(|chunk| {
+            if crc8(&chunk[..2]) != chunk[2] {
+                bail!("CRC mismatch in answer to command {cmd:#06x}");
+            }
+            Ok(u16::from_be_bytes([chunk[0], chunk[1]]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+    use super::*;
+
+    /// Address and bytes of every write, in order.
+    type Writes = Arc<Mutex<Vec<(u8, Vec<u8>)>>>;
+
+    /// Bus recording every write and answering reads from a queue.
+    #[derive(Default)]
+    struct MockI2c {
+        writes: Writes,
+        reads: VecDeque<Vec<u8>>,
+        /// Write not acknowledged until the second one has been made, like
+        /// a start while the sensor is already measuring.
+        refused: Option<(Vec<u8>, Vec<u8>)>,
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        match &self.refused {
+                            Some((refused, _)) if refused == bytes => {
+                                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data));
+                            }
+                            Some((_, unlock)) if unlock == bytes => self.refused = None,
+                            _ => (),
+                        }
+                        self.writes.lock().unwrap().push((address, bytes.to_vec()));
+                    }
+                    Operation::Read(buf) => {
+                        let answer = self.reads.pop_front().ok_or(ErrorKind::Other)?;
+                        buf.copy_from_slice(&answer);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// Answer made of `words`, each followed by its CRC.
+    fn answer(words: &[u16]) -> Vec<u8> {
+        command(0, words).split_off(2)
+    }
+
+    fn mock(reads: Vec<Vec<u8>>) -> (MockI2c, Writes) {
+        let i2c = MockI2c {
+            reads: reads.into(),
+            ..Default::default()
+        };
+        let writes = i2c.writes.clone();
+        (i2c, writes)
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() < 0.01, "{value} != {expected}");
+    }
+
+    #[test]
+    fn crc_matches_datasheet_example() {
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn sht3x_measures_single_shot() {
+        let (i2c, writes) = mock(vec![answer(&[0x6666, 0x8000])]);
+        let mut sensor = sht3x::Sht3x::new(i2c, NoDelay, sht3x::ADDRESS);
+        let reading = sensor.read().unwrap().unwrap();
+        assert_eq!(*writes.lock().unwrap(), vec![(0x44, vec![0x24, 0x00])]);
+        assert_close(reading.temperature_c, 25.0);
+        assert_close(reading.humidity_pct, 50.0);
+        assert_eq!(reading.co2_ppm, None);
+    }
+
+    #[test]
+    fn sht3x_rejects_bad_crc() {
+        let mut corrupted = answer(&[0x6666, 0x8000]);
+        corrupted[2] ^= 0xff;
+        let (i2c, _) = mock(vec![corrupted]);
+        let mut sensor = sht3x::Sht3x::new(i2c, NoDelay, sht3x::ADDRESS);
+        let err = sensor.read().unwrap_err();
+        assert!(err.to_string().contains("CRC"), "{err}");
+    }
+
+    #[test]
+    fn scd30_waits_for_data_ready() {
+        let float = |value: f32| {
+            let bits = value.to_bits();
+            [(bits >> 16) as u16, bits as u16]
+        };
+        let measurement: Vec<u16> = [float(400.0), float(21.5), float(45.0)].concat();
+        let (i2c, writes) = mock(vec![answer(&[0]), answer(&[1]), answer(&measurement)]);
+        let mut sensor = scd30::Scd30::new(i2c, NoDelay, scd30::ADDRESS);
+
+        assert_eq!(sensor.read().unwrap(), None);
+        let reading = sensor.read().unwrap().unwrap();
+        assert_eq!(
+            *writes.lock().unwrap(),
+            vec![
+                (0x61, vec![0x00, 0x10, 0x00, 0x00, 0x81]),
+                (0x61, vec![0x02, 0x02]),
+                (0x61, vec![0x02, 0x02]),
+                (0x61, vec![0x03, 0x00]),
+            ]
+        );
+        assert_eq!(reading.co2_ppm, Some(400.0));
+        assert_eq!(reading.temperature_c, Some(21.5));
+        assert_eq!(reading.humidity_pct, Some(45.0));
+    }
+
+    #[test]
+    fn scd30_rejects_bad_crc() {
+        let mut corrupted = answer(&[1]);
+        corrupted[2] ^= 0xff;
+        let (i2c, _) = mock(vec![corrupted]);
+        let mut sensor = scd30::Scd30::new(i2c, NoDelay, scd30::ADDRESS);
+        assert!(sensor.read().is_err());
+    }
+
+    #[test]
+    fn scd4x_waits_for_data_ready() {
+        let (i2c, writes) = mock(vec![
+            answer(&[0x8000]),
+            answer(&[0x0006]),
+            answer(&[800, 0x6666, 0x8000]),
+        ]);
+        let mut sensor = scd4x::Scd4x::new(i2c, NoDelay, scd4x::ADDRESS);
+
+        // Bits above the low 11 do not mean a sample is ready.
+        assert_eq!(sensor.read().unwrap(), None);
+        let reading = sensor.read().unwrap().unwrap();
+        assert_eq!(
+            *writes.lock().unwrap(),
+            vec![
+                (0x62, vec![0x3f, 0x86]),
+                (0x62, vec![0x21, 0xb1]),
+                (0x62, vec![0xe4, 0xb8]),
+                (0x62, vec![0xe4, 0xb8]),
+                (0x62, vec![0xec, 0x05]),
+            ]
+        );
+        assert_eq!(reading.co2_ppm, Some(800.0));
+        assert_close(reading.temperature_c, 25.0);
+        assert_close(reading.humidity_pct, 50.0);
+    }
+
+    #[test]
+    fn scd4x_stops_measurements_left_running() {
+        let (mut i2c, writes) = mock(vec![answer(&[0x0006]), answer(&[800, 0x6666, 0x8000])]);
+        i2c.refused = Some((vec![0x21, 0xb1], vec![0x3f, 0x86]));
+        let mut sensor = scd4x::Scd4x::new(i2c, NoDelay, scd4x::ADDRESS);
+
+        let reading = sensor.read().unwrap().unwrap();
+        assert_eq!(
+            writes.lock().unwrap()[..2],
+            [(0x62, vec![0x3f, 0x86]), (0x62, vec![0x21, 0xb1])]
+        );
+        assert_eq!(reading.co2_ppm, Some(800.0));
+    }
+
+    #[test]
+    fn scd4x_rejects_bad_crc() {
+        let mut corrupted = answer(&[0x0006]);
+        corrupted[2] ^= 0xff;
+        let (i2c, _) = mock(vec![corrupted]);
+        let mut sensor = scd4x::Scd4x::new(i2c, NoDelay, scd4x::ADDRESS);
+        assert!(sensor.read().is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use super::{command, i2c_error, query, Reading, Sensor};
+
+pub const ADDRESS: u8 = 0x61;
+/// Takes the ambient pressure in mbar as argument, 0 disabling the
+/// compensation.
+const START_CONTINUOUS: u16 = 0x0010;
+const DATA_READY: u16 = 0x0202;
+const READ_MEASUREMENT: u16 = 0x0300;
+/// The SCD30 needs a pause between a command and reading its answer.
+const WAIT_MS: u32 = 3;
+
+/// Sensirion SCD30 NDIR CO2 sensor, also reporting temperature and humidity.
+pub struct Scd30<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    started: bool,
+}
+
+impl<I: I2c, D: DelayNs> Scd30<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self {
+            i2c,
+            delay,
+            address,
+            started: false,
+        }
+    }
+}
+
+/// Measurements are big-endian IEEE 754 floats split over two words.
+fn float(high: u16, low: u16) -> f64 {
+    f32::from_bits((high as u32) << 16 | low as u32) as f64
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Scd30<I, D> {
+    fn read(&mut self) -> anyhow::Result<Option<Reading>> {
+        if !self.started {
+            self.i2c
+                .write(self.address, &command(START_CONTINUOUS, &[0]))
+                .map_err(i2c_error)?;
+            self.started = true;
+        }
+        let ready = query(
+            &mut self.i2c,
+            &mut self.delay,
+            self.address,
+            DATA_READY,
+            WAIT_MS,
+            1,
+        )?;
+        if ready[0] != 1 {
+            return Ok(None);
+        }
+        let w = query(
+            &mut self.i2c,
+            &mut self.delay,
+            self.address,
+            READ_MEASUREMENT,
+            WAIT_MS,
+            6,
+        )?;
+        Ok(Some(Reading {
+            co2_ppm: Some(float(w[0], w[1])),
+            temperature_c: Some(float(w[2], w[3])),
+            humidity_pct: Some(float(w[4], w[5])),
+        }))
+    }
+}
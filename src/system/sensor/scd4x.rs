@@ -0,0 +1,77 @@
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use super::{command, i2c_error, query, Reading, Sensor};
+
+pub const ADDRESS: u8 = 0x62;
+/// A new sample every 5 s.
+const START_PERIODIC: u16 = 0x21b1;
+const STOP_PERIODIC: u16 = 0x3f86;
+/// Time the sensor needs to go idle after [`STOP_PERIODIC`].
+const STOP_MS: u32 = 500;
+const DATA_READY: u16 = 0xe4b8;
+const READ_MEASUREMENT: u16 = 0xec05;
+const WAIT_MS: u32 = 1;
+
+/// Sensirion SCD40/41 photoacoustic CO2 sensor, also reporting temperature
+/// and humidity.
+pub struct Scd4x<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    started: bool,
+}
+
+impl<I: I2c, D: DelayNs> Scd4x<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self {
+            i2c,
+            delay,
+            address,
+            started: false,
+        }
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Scd4x<I, D> {
+    fn read(&mut self) -> anyhow::Result<Option<Reading>> {
+        if !self.started {
+            // Still measuring after a restart of the daemon alone, in which
+            // case it would refuse to start again. An idle sensor may refuse
+            // to stop, which is fine.
+            self.i2c
+                .write(self.address, &command(STOP_PERIODIC, &[]))
+                .ok();
+            self.delay.delay_ms(STOP_MS);
+            self.i2c
+                .write(self.address, &command(START_PERIODIC, &[]))
+                .map_err(i2c_error)?;
+            self.started = true;
+        }
+        let status = query(
+            &mut self.i2c,
+            &mut self.delay,
+            self.address,
+            DATA_READY,
+            WAIT_MS,
+            1,
+        )?;
+        // Only the 11 low bits tell whether a sample is waiting.
+        if status[0] & 0x07ff == 0 {
+            return Ok(None);
+        }
+        let w = query(
+            &mut self.i2c,
+            &mut self.delay,
+            self.address,
+            READ_MEASUREMENT,
+            WAIT_MS,
+            3,
+        )?;
+        Ok(Some(Reading {
+            co2_ppm: Some(w[0] as f64),
+            temperature_c: Some(-45.0 + 175.0 * w[1] as f64 / 65535.0),
+            humidity_pct: Some(100.0 * w[2] as f64 / 65535.0),
+        }))
+    }
+}
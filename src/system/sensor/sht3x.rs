@@ -0,0 +1,36 @@
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use super::{query, Reading, Sensor};
+
+pub const ADDRESS: u8 = 0x44;
+/// Single shot, high repeatability, without clock stretching.
+const MEASURE: u16 = 0x2400;
+
+/// Sensirion SHT30/31/35 temperature and humidity sensor.
+pub struct Sht3x<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+}
+
+impl<I: I2c, D: DelayNs> Sht3x<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self {
+            i2c,
+            delay,
+            address,
+        }
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Sht3x<I, D> {
+    fn read(&mut self) -> anyhow::Result<Option<Reading>> {
+        let words = query(&mut self.i2c, &mut self.delay, self.address, MEASURE, 16, 2)?;
+        Ok(Some(Reading {
+            temperature_c: Some(-45.0 + 175.0 * words[0] as f64 / 65535.0),
+            humidity_pct: Some(100.0 * words[1] as f64 / 65535.0),
+            co2_ppm: None,
+        }))
+    }
+}
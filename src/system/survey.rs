@@ -0,0 +1,213 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::bail;
+
+use super::actuator::{TravelLimits, CAMERA};
+use super::camera::{field_of_view, PixelTransform, SurveyConfig};
+use crate::database::{self, SurveyProposalData};
+
+/// Proposal for a position where a plant was found.
+pub const NEW: &str = "new";
+/// Proposal to remove a position where nothing was found.
+pub const MISSING: &str = "missing";
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Plant seen in one or more frames, in bed mm.
+struct Cluster {
+    x: f64,
+    y: f64,
+    class: String,
+    seen: u32,
+}
+
+impl Cluster {
+    fn distance(&self, x: f64, y: f64) -> f64 {
+        (self.x - x).hypot(self.y - y)
+    }
+
+    fn add(&mut self, x: f64, y: f64) {
+        self.seen += 1;
+        self.x += (x - self.x) / self.seen as f64;
+        self.y += (y - self.y) / self.seen as f64;
+    }
+}
+
+/// Camera positions `step` apart over the whole reachable bed, row by row in
+/// alternating directions.
+fn raster(limits: &TravelLimits, step: u32, capture_z: Option<u32>) -> Vec<(u32, u32)> {
+    let offset = limits.tools.get(CAMERA).copied().unwrap_or_default();
+    let axis = |offset: i32, limit: u32| {
+        let (first, last) = (
+            offset.max(0) as u32,
+            (limit as i64 + offset as i64).max(0) as u32,
+        );
+        let mut values: Vec<u32> = (first..=last).step_by(step as usize).collect();
+        if values.last().is_some_and(|&v| v < last) {
+            values.push(last);
+        }
+        values
+    };
+    let (xs, ys) = (
+        axis(offset.x, limits.x_limit),
+        axis(offset.y, limits.y_limit),
+    );
+
+    let mut frames = Vec::new();
+    for (row, &y) in ys.iter().enumerate() {
+        let mut row_xs = xs.clone();
+        if row % 2 == 1 {
+            row_xs.reverse();
+        }
+        frames.extend(
+            row_xs
+                .into_iter()
+                .filter(|&x| limits.validate_tool(CAMERA, x, y, capture_z).is_ok())
+                .map(|x| (x, y)),
+        );
+    }
+    frames
+}
+
+/// Scans the bed in the background, then replaces the pending proposals
+/// with what it found.
+pub async fn start(config: SurveyConfig) -> anyhow::Result<()> {
+    let Some(transform) = super::pixel_transform(config.capture_z).await else {
+        bail!("the camera has no pixel to mm transform at the survey height, calibrate it first");
+    };
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        bail!("a survey is already running");
+    }
+    async_std::task::spawn(async move {
+        if let Err(e) = survey(config, transform).await {
+            log::error!("Survey failed: {e}");
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+async fn survey(config: SurveyConfig, transform: PixelTransform) -> anyhow::Result<()> {
+    let (fov_x, fov_y) = field_of_view(transform);
+    let step = (fov_x.min(fov_y) * (1.0 - config.overlap.clamp(0.0, 0.9))).max(1.0) as u32;
+    let limits = super::LIMITS
+        .lock()
+        .await
+        .clone()
+        .expect("init must be called");
+    let frames = raster(&limits, step, config.capture_z);
+    log::info!("Surveying {} frames {step} mm apart", frames.len());
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (i, &(x, y)) in frames.iter().enumerate() {
+        let (image, _) = super::capture_cropped(x, y, config.capture_z).await?;
+        let detections = super::DETECTOR
+            .lock()
+            .await
+            .as_mut()
+            .expect("init must be called")
+            .detect_all(&image)
+            .await?;
+        log::info!(
+            "Survey frame {}/{} at ({x}, {y}): {} detection(s)",
+            i + 1,
+            frames.len(),
+            detections.len()
+        );
+        let half = image.width() as f64 / 2.0;
+        for detection in detections {
+            let (cx, cy) = detection.center();
+            let (dx, dy) = transform.to_mm(cx as f64 - half, cy as f64 - half);
+            let (bx, by) = (x as f64 + dx, y as f64 + dy);
+            match clusters
+                .iter_mut()
+                .find(|cluster| cluster.distance(bx, by) <= config.merge_mm)
+            {
+                Some(cluster) => cluster.add(bx, by),
+                None => clusters.push(Cluster {
+                    x: bx,
+                    y: by,
+                    class: detection.class,
+                    seen: 1,
+                }),
+            }
+        }
+    }
+
+    let positions = database::query_position(None, None).await?;
+    let created_ts = super::timestamp();
+    let mut proposals = Vec::new();
+    for cluster in &clusters {
+        let known = positions
+            .iter()
+            .any(|pos| cluster.distance(pos.x as f64, pos.y as f64) <= config.merge_mm);
+        if !known {
+            proposals.push(SurveyProposalData {
+                id: 0,
+                kind: NEW.to_owned(),
+                x: cluster.x.round().max(0.0) as i64,
+                y: cluster.y.round().max(0.0) as i64,
+                class: Some(cluster.class.clone()),
+                position_id: None,
+                created_ts,
+            });
+        }
+    }
+    for pos in &positions {
+        let (x, y) = (pos.x as f64, pos.y as f64);
+        let surveyed = frames.iter().any(|&(fx, fy)| {
+            (fx as f64 - x).abs() <= fov_x / 2.0 && (fy as f64 - y).abs() <= fov_y / 2.0
+        });
+        let found = clusters
+            .iter()
+            .any(|cluster| cluster.distance(x, y) <= config.merge_mm);
+        if surveyed && !found {
+            proposals.push(SurveyProposalData {
+                id: 0,
+                kind: MISSING.to_owned(),
+                x: pos.x,
+                y: pos.y,
+                class: None,
+                position_id: Some(pos.id),
+                created_ts,
+            });
+        }
+    }
+
+    database::delete_survey_proposals().await?;
+    for proposal in proposals {
+        log::info!(
+            "Survey proposes {} position at ({}, {})",
+            proposal.kind,
+            proposal.x,
+            proposal.y
+        );
+        database::insert_survey_proposal(proposal).await?;
+    }
+    log::info!("Survey done: {} plant(s) found", clusters.len());
+    Ok(())
+}
+
+/// Applies or drops a proposal: approving `new` adds the position, approving
+/// `missing` removes it.
+pub async fn resolve(id: i64, approve: bool, capture_z: Option<u32>) -> anyhow::Result<()> {
+    let Some(proposal) = database::query_survey_proposals(Some(id)).await?.pop() else {
+        bail!("no survey proposal {id}");
+    };
+    if approve {
+        match (proposal.kind.as_str(), proposal.position_id) {
+            (NEW, _) => {
+                let (x, y) = (proposal.x as u32, proposal.y as u32);
+                super::validate_position(x, y, capture_z, None).await?;
+                let id = database::upsert_position(x, y).await?;
+                database::update_position_heights(id, capture_z, None).await?;
+            }
+            (MISSING, Some(position_id)) => {
+                database::delete_position(position_id).await?;
+            }
+            (kind, _) => bail!("unknown survey proposal kind `{kind}`"),
+        }
+    }
+    database::delete_survey_proposal(id).await?;
+    Ok(())
+}
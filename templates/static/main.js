@@ -139,6 +139,48 @@ const gotoPosition = async (x, y) => {
   document.querySelector("#loading").style.display = 'none';
 }
 
+const calibrateCamera = async () => {
+  document.querySelector("#camera-stream").style.display = '';
+  let response = await fetch("/action/calibrate");
+  alert(await response.text());
+  window.location.reload();
+}
+
+const startSurvey = async () => {
+  let response = await fetch("/action/survey");
+  if (response.ok) {
+    alert("Survey started, its proposals will be listed here once it is done");
+  } else {
+    alert(await response.text());
+  }
+}
+
+const previewGrid = async () => {
+  let form = new URLSearchParams(new FormData(document.querySelector("#grid-form")));
+  form.append("preview", "true");
+  let response = await fetch("/create/grid", { method: "POST", body: form });
+  if (!response.ok) {
+    alert(await response.text());
+    return;
+  }
+  let grid = await response.json();
+  let [fw, fh] = grid.fov || [0, 0];
+  let xs = grid.positions.map((p) => p.x);
+  let ys = grid.positions.map((p) => p.y);
+  let x0 = Math.min(...xs) - fw / 2, y0 = Math.min(...ys) - fh / 2;
+  let w = Math.max(...xs) - Math.min(...xs) + fw || 1, h = Math.max(...ys) - Math.min(...ys) + fh || 1;
+  let r = Math.max(w, h) / 100;
+  let shapes = grid.positions.map((p) => {
+    let color = p.skip === null ? "hsl(204, 86%, 53%)" : "hsl(348, 100%, 61%)";
+    let frame = fw ? `<rect x="${p.x - fw / 2}" y="${p.y - fh / 2}" width="${fw}" height="${fh}" fill="${color}" fill-opacity="0.15" stroke="${color}" stroke-width="${r / 4}"/>` : "";
+    return frame + `<circle cx="${p.x}" cy="${p.y}" r="${r}" fill="${color}"><title>${p.x}, ${p.y}${p.skip === null ? "" : ": " + p.skip}</title></circle>`;
+  });
+  let skipped = grid.positions.filter((p) => p.skip !== null).length;
+  document.querySelector("#grid-preview").innerHTML =
+    `<p>${grid.positions.length - skipped} position(s) to create, ${skipped} skipped</p>` +
+    `<svg viewBox="${x0 - r} ${y0 - r} ${w + 2 * r} ${h + 2 * r}" style="width: 100%; max-height: 300px;">${shapes.join("")}</svg>`;
+}
+
 const recheck = async (id) => {
   await fetch("/action/recheck?id=" + id);
 }
@@ -162,6 +204,23 @@ const waterPosition = async (id) => {
   document.querySelector("#loading").style.display = 'none';
   window.location.reload();
 }
+const emergencyStop = async () => {
+  await fetch("/action/estop");
+}
+
+const resetEmergencyStop = async () => {
+  let response = await fetch("/action/estop/reset");
+  if (!response.ok) {
+    alert(await response.text());
+  }
+}
+const refillReservoir = async () => {
+  let response = await fetch("/action/reservoir/refill");
+  if (!response.ok) {
+    alert(await response.text());
+  }
+  window.location.reload();
+}
 const checkAll = async () => {
   document.querySelector("#loading").style.display = '';
 